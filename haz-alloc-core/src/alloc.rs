@@ -1,52 +1,147 @@
 use crate::__internal::UsizeExt;
 use crate::backend::Backend;
-use crate::reserve::{ReserveHeader, ReserveType, RESERVE_ALIGN};
-use crate::{huge, subhuge};
+use crate::config::{Config, DefaultConfig, Tuned, With, MAX_CLASSES};
+use crate::hooks::{self, AllocHooks, NoHooks};
+use crate::leak::Report;
+use crate::reserve::{self, ReserveHeader, ReserveType};
+use crate::stats::{ArenaStats, Stats};
+use crate::walk::{BlockInfo, BlockKind};
+use crate::{budget, check, config, debug, huge, quarantine, subhuge};
+#[cfg(feature = "allocator_api")]
+use core::alloc::{AllocError, Allocator};
 use core::alloc::{GlobalAlloc, Layout};
 use core::marker::PhantomData;
-use core::{cmp, ptr};
+#[cfg(feature = "allocator_api")]
+use core::ptr::NonNull;
+use core::{cmp, fmt, ptr};
 
-pub struct Alloc<B> {
+/// The allocator, calling the hooks `H` on each allocation, deallocation
+/// and reallocation, and tuned by the config `C`.
+pub struct Alloc<B, H = NoHooks, C = DefaultConfig> {
     _backend: PhantomData<B>,
+    _hooks: PhantomData<H>,
+    _config: PhantomData<C>,
 }
 
-impl<B> Copy for Alloc<B> {}
+impl<B, H, C> Copy for Alloc<B, H, C> {}
 
-impl<B> Clone for Alloc<B> {
+impl<B, H, C> Clone for Alloc<B, H, C> {
     #[inline]
     fn clone(&self) -> Self {
-        Self {
-            _backend: PhantomData,
-        }
+        *self
     }
 }
 
-impl<B> Alloc<B> {
+impl<B: Backend> Alloc<B> {
     /// Create a new `Alloc`.
     ///
     /// # Safety
     ///
     /// All `Alloc::new` must be called with the same backend.
     pub const unsafe fn new() -> Self {
+        Self::with_hooks()
+    }
+}
+
+impl<B: Backend, H> Alloc<B, H> {
+    /// Create a new `Alloc` calling the hooks `H`.
+    ///
+    /// # Safety
+    ///
+    /// All `Alloc::with_hooks` must be called with the same backend.
+    pub const unsafe fn with_hooks() -> Self {
+        Self::with_config()
+    }
+}
+
+impl<B: Backend, H, C: Config> Alloc<B, H, C> {
+    /// Create a new `Alloc` calling the hooks `H` and tuned by `C`.
+    ///
+    /// The build fails if `C` is out of range.
+    ///
+    /// # Safety
+    ///
+    /// All `Alloc::with_config` must be called with the same backend and
+    /// config.
+    pub const unsafe fn with_config() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = <With<B, C> as Tuned>::VALID;
         Self {
             _backend: PhantomData,
+            _hooks: PhantomData,
+            _config: PhantomData,
         }
     }
 }
 
-impl<B: Backend> Alloc<B> {
+/// Whether blocks for `layout` are allocated in their own reservation.
+#[inline]
+pub(crate) fn is_huge<B: Tuned>(layout: Layout) -> bool {
+    layout.size() > config::huge_threshold::<B>() || layout.align() > B::pagesize()
+}
+
+/// # Safety
+///
+/// Layout must be valid.
+#[inline]
+pub(crate) unsafe fn alloc_raw<B: Tuned>(layout: Layout, zeroed: bool) -> *mut u8 {
+    if is_huge::<B>(layout) {
+        huge::alloc::<B>(layout)
+    } else {
+        subhuge::alloc::<B>(layout, zeroed)
+    }
+}
+
+/// Deallocate a block without any check.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+pub(crate) unsafe fn dealloc_raw<B: Tuned>(ptr: *mut u8) {
+    let header = (ptr as usize - 1).align_down(B::RESERVE_ALIGN) as *mut ReserveHeader;
+    match (*header).ty {
+        ReserveType::Huge => huge::dealloc::<B>(header),
+        ReserveType::SubHuge => subhuge::dealloc::<B>(header, ptr),
+    }
+}
+
+/// Returns the usable size of a block, including the redzone in debug
+/// mode.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+pub(crate) unsafe fn usable_size<B: Tuned>(ptr: *mut u8) -> usize {
+    let header = (ptr as usize - 1).align_down(B::RESERVE_ALIGN) as *mut ReserveHeader;
+    match (*header).ty {
+        ReserveType::Huge => huge::size(header, ptr),
+        ReserveType::SubHuge => subhuge::size::<B>(ptr),
+    }
+}
+
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn block_kind<B: Tuned>(ptr: *mut u8) -> BlockKind {
+    let header = (ptr as usize - 1).align_down(B::RESERVE_ALIGN) as *mut ReserveHeader;
+    match (*header).ty {
+        ReserveType::Huge => BlockKind::Huge,
+        ReserveType::SubHuge if subhuge::is_small::<B>(ptr) => BlockKind::Small,
+        ReserveType::SubHuge => BlockKind::Large,
+    }
+}
+
+impl<B: Backend, H: AllocHooks, C: Config> Alloc<B, H, C> {
     /// # Safety
     ///
     /// Layout must be valid.
     #[inline]
     pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if layout.align() > RESERVE_ALIGN {
-            ptr::null_mut()
-        } else if layout.size() > subhuge::MAX || layout.align() > B::pagesize() {
-            huge::alloc::<B>(layout)
-        } else {
-            subhuge::alloc::<B>(layout, false)
-        }
+        let ptr = self.alloc_with(layout, false);
+        hooks::alloc::<With<B, C>, H>(ptr);
+        ptr
     }
 
     /// # Safety
@@ -54,13 +149,22 @@ impl<B: Backend> Alloc<B> {
     /// Layout must be valid.
     #[inline]
     pub unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        if layout.align() > RESERVE_ALIGN {
-            ptr::null_mut()
-        } else if layout.size() > subhuge::MAX || layout.align() > B::pagesize() {
-            huge::alloc::<B>(layout)
-        } else {
-            subhuge::alloc::<B>(layout, true)
+        let ptr = self.alloc_with(layout, true);
+        hooks::alloc::<With<B, C>, H>(ptr);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_with(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let padded = match debug::pad::<With<B, C>>(layout) {
+            Some(x) => x,
+            None => return ptr::null_mut(),
+        };
+        let ptr = budget::with_retries(layout, || alloc_raw::<With<B, C>>(padded, zeroed));
+        if C::DEBUG && !ptr.is_null() {
+            debug::on_alloc::<With<B, C>>(ptr, layout.size(), zeroed);
         }
+        ptr
     }
 
     /// # Safety
@@ -69,27 +173,102 @@ impl<B: Backend> Alloc<B> {
     ///
     /// Alignment must match of original allocation.
     pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
-        let header = (ptr as usize - 1).align_down(RESERVE_ALIGN) as *mut ReserveHeader;
-        match (*header).ty {
-            ReserveType::Huge => {
-                if huge::realloc_in_place::<B>(header, ptr, layout) {
-                    return ptr;
-                }
+        let new = if self.realloc_in_place(ptr, layout) {
+            ptr
+        } else {
+            let new = self.alloc_with(layout, false);
+            if new.is_null() {
+                return ptr::null_mut();
             }
-            ReserveType::SubHuge => {
-                if subhuge::realloc_in_place::<B>(header, ptr, layout) {
-                    return ptr;
-                }
+            new.copy_from_nonoverlapping(ptr, cmp::min(layout.size(), self.size(ptr)));
+            self.dealloc_with(ptr, false);
+            new
+        };
+        hooks::realloc::<With<B, C>, H>(ptr, new);
+        new
+    }
+
+    /// Grow a block without moving it, returning the new usable size, or
+    /// `None` if there is no room to grow in place.
+    ///
+    /// A block that already fits `new_layout` is left as is.
+    ///
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub unsafe fn try_grow_in_place(&self, ptr: *mut u8, new_layout: Layout) -> Option<usize> {
+        let size = self.size(ptr);
+        if new_layout.size() <= size {
+            return Some(size);
+        }
+        if self.realloc_in_place(ptr, new_layout) {
+            hooks::realloc::<With<B, C>, H>(ptr, ptr);
+            Some(self.size(ptr))
+        } else {
+            None
+        }
+    }
+
+    /// Shrink a block without moving it, returning the new usable size, or
+    /// `None` if it cannot be shrunk in place or `new_layout` is larger than
+    /// the block, in which case the block still has its original layout.
+    ///
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub unsafe fn shrink_in_place(&self, ptr: *mut u8, new_layout: Layout) -> Option<usize> {
+        if new_layout.size() > self.size(ptr) {
+            return None;
+        }
+        if self.realloc_in_place(ptr, new_layout) {
+            hooks::realloc::<With<B, C>, H>(ptr, ptr);
+            Some(self.size(ptr))
+        } else {
+            None
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub(crate) unsafe fn realloc_in_place(&self, ptr: *mut u8, layout: Layout) -> bool {
+        if C::DEBUG {
+            let old_size = debug::check::<With<B, C>>(ptr);
+            let padded = match debug::pad::<With<B, C>>(layout) {
+                Some(x) => x,
+                None => return false,
+            };
+            if !self.realloc_in_place_raw(ptr, padded) {
+                return false;
             }
+            debug::on_resize::<With<B, C>>(ptr, old_size, layout.size());
+            true
+        } else {
+            self.realloc_in_place_raw(ptr, layout)
         }
+    }
 
-        let new = self.alloc(layout);
-        if new.is_null() {
-            return ptr::null_mut();
+    #[inline]
+    unsafe fn realloc_in_place_raw(&self, ptr: *mut u8, layout: Layout) -> bool {
+        let header = (ptr as usize - 1).align_down(C::RESERVE_ALIGN) as *mut ReserveHeader;
+        match (*header).ty {
+            // Huge blocks never shrink into small layouts in place, so that
+            // `dealloc_sized` can trust small layouts to be in small pages.
+            ReserveType::Huge => {
+                subhuge::small_class::<With<B, C>>(layout).is_none()
+                    && huge::realloc_in_place::<With<B, C>>(header, layout)
+            }
+            ReserveType::SubHuge => subhuge::realloc_in_place::<With<B, C>>(header, ptr, layout),
         }
-        new.copy_from_nonoverlapping(ptr, cmp::min(layout.size(), self.size(ptr)));
-        self.dealloc(ptr);
-        new
     }
 
     /// # Safety
@@ -97,35 +276,313 @@ impl<B: Backend> Alloc<B> {
     /// Pointer must be valid.
     #[inline]
     pub unsafe fn dealloc(&self, ptr: *mut u8) {
-        let header = (ptr as usize - 1).align_down(RESERVE_ALIGN) as *mut ReserveHeader;
+        self.dealloc_with(ptr, true)
+    }
+
+    #[inline]
+    unsafe fn dealloc_with(&self, ptr: *mut u8, hooked: bool) {
+        let header = check::free::<With<B, C>>(ptr);
+        if H::ENABLED && hooked {
+            hooks::dealloc::<With<B, C>, H>(ptr);
+        }
+        if C::DEBUG {
+            debug::on_free::<With<B, C>>(ptr);
+        }
+        if quarantine::ENABLED && quarantine::push::<With<B, C>>(ptr) {
+            return;
+        }
         match (*header).ty {
-            ReserveType::Huge => huge::dealloc::<B>(header),
-            ReserveType::SubHuge => subhuge::dealloc::<B>(header, ptr),
+            ReserveType::Huge => huge::dealloc::<With<B, C>>(header),
+            ReserveType::SubHuge => subhuge::dealloc::<With<B, C>>(header, ptr),
         }
     }
 
+    /// Deallocate using the layout of the block to skip loading its metadata
+    /// where possible.
+    ///
+    /// Blocks are only checked against the class of the layout, and a layout
+    /// of another class aborts. In debug mode, the size must be the requested
+    /// size.
+    ///
     /// # Safety
     ///
     /// Pointer must be valid.
+    ///
+    /// Alignment must match of original allocation, and size must be between
+    /// the requested size and the usable size.
     #[inline]
-    pub unsafe fn size(&self, ptr: *mut u8) -> usize {
-        let header = (ptr as usize - 1).align_down(RESERVE_ALIGN) as *mut ReserveHeader;
+    pub unsafe fn dealloc_sized(&self, ptr: *mut u8, layout: Layout) {
+        // Blocks are allocated with the padded layout in debug mode.
+        let class = debug::pad::<With<B, C>>(layout).and_then(subhuge::small_class::<With<B, C>>);
+        let (header, page) = check::free_sized::<With<B, C>>(ptr, class);
+        hooks::dealloc::<With<B, C>, H>(ptr);
+        if C::DEBUG {
+            debug::on_free_sized::<With<B, C>>(ptr, layout);
+        }
+        if quarantine::ENABLED && quarantine::push::<With<B, C>>(ptr) {
+            return;
+        }
         match (*header).ty {
-            ReserveType::Huge => huge::size(header, ptr),
-            ReserveType::SubHuge => subhuge::size::<B>(ptr),
+            ReserveType::Huge => huge::dealloc::<With<B, C>>(header),
+            ReserveType::SubHuge => match class {
+                Some(class) => subhuge::dealloc_small::<With<B, C>>(header, page, ptr, class),
+                None => subhuge::dealloc_large::<With<B, C>>(header, page),
+            },
         }
     }
+
+    /// Allocate blocks for `layout` into `out`, returning how many were
+    /// allocated before running out of memory.
+    ///
+    /// Small blocks are taken from the arena holding its lock once for the
+    /// whole batch.
+    ///
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    pub unsafe fn alloc_batch(&self, layout: Layout, out: &mut [*mut u8]) -> usize {
+        let n = if let Some(class) =
+            debug::pad::<With<B, C>>(layout).and_then(subhuge::small_class::<With<B, C>>)
+        {
+            let n = subhuge::alloc_batch::<With<B, C>>(class, out);
+            if C::DEBUG {
+                for &ptr in &out[..n] {
+                    debug::on_alloc::<With<B, C>>(ptr, layout.size(), false);
+                }
+            }
+            n
+        } else {
+            let mut n = 0;
+            while n < out.len() {
+                out[n] = self.alloc_with(layout, false);
+                if out[n].is_null() {
+                    break;
+                }
+                n += 1;
+            }
+            n
+        };
+
+        if H::ENABLED {
+            for &ptr in &out[..n] {
+                hooks::alloc::<With<B, C>, H>(ptr);
+            }
+        }
+        n
+    }
+
+    /// Deallocate all blocks in `ptrs`.
+    ///
+    /// Pointers are sorted in chunks of 64, so that small blocks of the same
+    /// page are freed at once whatever their order in `ptrs`.
+    ///
+    /// # Safety
+    ///
+    /// Pointers must be valid.
+    pub unsafe fn dealloc_batch(&self, ptrs: &[*mut u8]) {
+        for &ptr in ptrs {
+            check::free::<With<B, C>>(ptr);
+            if H::ENABLED {
+                hooks::dealloc::<With<B, C>, H>(ptr);
+            }
+            if C::DEBUG {
+                debug::on_free::<With<B, C>>(ptr);
+            }
+        }
+        if quarantine::ENABLED {
+            for &ptr in ptrs {
+                if !quarantine::push::<With<B, C>>(ptr) {
+                    dealloc_raw::<With<B, C>>(ptr);
+                }
+            }
+            return;
+        }
+
+        let mut buf = [ptr::null_mut(); 64];
+        for chunk in ptrs.chunks(buf.len()) {
+            let sorted = &mut buf[..chunk.len()];
+            sorted.copy_from_slice(chunk);
+            sorted.sort_unstable();
+
+            let mut i = 0;
+            while i < sorted.len() {
+                let ptr = sorted[i];
+                let header = (ptr as usize - 1).align_down(C::RESERVE_ALIGN) as *mut ReserveHeader;
+                i += match (*header).ty {
+                    ReserveType::Huge => {
+                        huge::dealloc::<With<B, C>>(header);
+                        1
+                    }
+                    ReserveType::SubHuge => {
+                        match subhuge::dealloc_batch::<With<B, C>>(header, &sorted[i..]) {
+                            0 => {
+                                subhuge::dealloc::<With<B, C>>(header, ptr);
+                                1
+                            }
+                            n => n,
+                        }
+                    }
+                };
+            }
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    pub unsafe fn size(&self, ptr: *mut u8) -> usize {
+        if C::DEBUG {
+            debug::size::<With<B, C>>(ptr)
+        } else {
+            usable_size::<With<B, C>>(ptr)
+        }
+    }
+
+    /// Returns the usable size of the block that would be allocated for
+    /// `layout`, without allocating.
+    #[inline]
+    pub fn good_size(&self, layout: Layout) -> usize {
+        if C::DEBUG {
+            layout.size()
+        } else if is_huge::<With<B, C>>(layout) {
+            huge::good_size::<With<B, C>>(layout)
+        } else {
+            subhuge::good_size::<With<B, C>>(layout)
+        }
+    }
+
+    /// Release the cached arenas of exited threads that have no block left,
+    /// returning the bytes given back to the system.
+    ///
+    /// The quarantine is emptied first.
+    pub fn purge(&self) -> usize {
+        quarantine::drain::<With<B, C>>();
+        subhuge::purge::<With<B, C>>()
+    }
+
+    /// Give up the arena of the current thread, as when the thread exits,
+    /// returning the bytes given back to the system.
+    ///
+    /// Meant for threads going idle. The thread takes an arena again on its
+    /// next allocation. The quarantine is emptied first.
+    pub fn flush(&self) -> usize {
+        quarantine::drain::<With<B, C>>();
+        subhuge::flush::<With<B, C>>()
+    }
+
+    /// Call `f` with each live block, including the blocks of every heap.
+    ///
+    /// The cache of the current thread and the quarantine are flushed
+    /// first, but blocks in the cache of other threads, or freed by a thread
+    /// other than the owner of their arena and not yet taken back, are
+    /// reported as live.
+    ///
+    /// `f` must not allocate or deallocate, as arenas cannot be created or
+    /// released until it returns.
+    pub fn walk(&self, mut f: impl FnMut(&BlockInfo)) {
+        let mut f = |info: &BlockInfo| {
+            if C::DEBUG {
+                let size = unsafe { debug::walked_size(info.ptr, info.size) };
+                f(&BlockInfo { size, ..*info })
+            } else {
+                f(info)
+            }
+        };
+        quarantine::drain::<With<B, C>>();
+        subhuge::walk::<With<B, C>>(&mut f);
+        huge::walk(&mut f);
+    }
+
+    /// Write a report of the live blocks, grouped by arena and size class,
+    /// returning how many there are.
+    ///
+    /// Nothing is written if there is no live block. Like `walk`, blocks in
+    /// the cache of other threads are reported as live, and `w` must not
+    /// allocate or deallocate.
+    pub fn leak_report(&self, w: &mut impl fmt::Write) -> Result<usize, fmt::Error> {
+        let mut report = Report::new(w, C::SMALL_CLASSES);
+        self.walk(|info| report.add(info));
+        report.finish()
+    }
+
+    /// Returns the statistics of the allocator.
+    ///
+    /// Arenas are shared by all `Alloc` and `Heap`, so this includes the
+    /// blocks of every heap. Blocks in the quarantine count as live. Small
+    /// blocks allocated or deallocated through the cache of another thread
+    /// may not be counted yet.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            small: [0; MAX_CLASSES],
+            large: 0,
+            huge: huge::live(),
+            committed_pages: 0,
+            committed: budget::committed(),
+            reserved: reserve::reserved(),
+            arenas_in_use: 0,
+            arenas_parked: 0,
+        };
+        subhuge::arena_stats::<With<B, C>>(|arena| {
+            for (x, y) in stats.small.iter_mut().zip(arena.small.iter()) {
+                *x += y;
+            }
+            stats.large += arena.large;
+            stats.committed_pages += arena.committed_pages;
+            if arena.parked {
+                stats.arenas_parked += 1;
+            } else {
+                stats.arenas_in_use += 1;
+            }
+        });
+        stats
+    }
+
+    /// Call `f` with the statistics of each arena.
+    ///
+    /// `f` must not allocate or deallocate, as arenas cannot be created or
+    /// released until it returns.
+    pub fn arena_stats(&self, f: impl FnMut(&ArenaStats)) {
+        subhuge::arena_stats::<With<B, C>>(f)
+    }
+
+    /// Set the most bytes of freed blocks held by the quarantine, in place
+    /// of `RuntimeConfig::quarantine`. Blocks larger than it are never held.
+    #[cfg(feature = "quarantine")]
+    pub fn set_quarantine_size(&self, bytes: usize) {
+        quarantine::set_size(bytes)
+    }
+
+    /// Set the most bytes committed at once by every arena and huge block,
+    /// or lift the limit if `None`.
+    ///
+    /// Allocations needing to commit beyond the limit call the OOM handler,
+    /// and return null unless it asks for a retry. A limit below the bytes
+    /// already committed only fails the next commits.
+    pub fn set_memory_limit(&self, bytes: Option<usize>) {
+        budget::set_limit(bytes)
+    }
+
+    /// Set the function called with the layout of an allocation that
+    /// failed, or remove it if `None`.
+    ///
+    /// It may free memory and return `true` to retry the allocation, at most
+    /// 4 times, after which the allocation returns null. It is not called
+    /// again for the allocations it makes.
+    pub fn set_oom_handler(&self, f: Option<fn(Layout) -> bool>) {
+        budget::set_handler(f)
+    }
 }
 
-unsafe impl<B: Backend> GlobalAlloc for Alloc<B> {
+unsafe impl<B: Backend, H: AllocHooks, C: Config> GlobalAlloc for Alloc<B, H, C> {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.alloc(layout)
     }
 
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
-        self.dealloc(ptr)
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_sized(ptr, layout)
     }
 
     #[inline]
@@ -141,3 +598,126 @@ unsafe impl<B: Backend> GlobalAlloc for Alloc<B> {
         )
     }
 }
+
+#[cfg(feature = "allocator_api")]
+impl<B: Backend, H: AllocHooks, C: Config> Alloc<B, H, C> {
+    /// Returns the whole usable block starting at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be null or valid.
+    #[inline]
+    pub(crate) unsafe fn block(&self, ptr: *mut u8) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, self.size(ptr.as_ptr())))
+    }
+
+    /// Resize in place if possible, otherwise move the block with `alloc`
+    /// and `dealloc`.
+    ///
+    /// # Safety
+    ///
+    /// See `Allocator::grow` and `Allocator::shrink`.
+    pub(crate) unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+        alloc: impl FnOnce(Layout) -> *mut u8,
+        dealloc: impl FnOnce(*mut u8),
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = ptr.as_ptr();
+        let new =
+            if old_layout.align() == new_layout.align() && self.realloc_in_place(ptr, new_layout) {
+                ptr
+            } else {
+                let new = alloc(new_layout);
+                if new.is_null() {
+                    return Err(AllocError);
+                }
+                new.copy_from_nonoverlapping(ptr, cmp::min(old_layout.size(), new_layout.size()));
+                dealloc(ptr);
+                new
+            };
+
+        hooks::realloc::<With<B, C>, H>(ptr, new);
+        let block = self.block(new)?;
+        if zeroed {
+            new.add(old_layout.size())
+                .write_bytes(0, block.len() - old_layout.size());
+        }
+        Ok(block)
+    }
+}
+
+/// Blocks are returned with their full usable size, and resizing is done in
+/// place whenever the alignment is unchanged and there is room for it.
+#[cfg(feature = "allocator_api")]
+unsafe impl<B: Backend, H: AllocHooks, C: Config> Allocator for Alloc<B, H, C> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.block(self.alloc(layout)) }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { self.block(self.alloc_zeroed(layout)) }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc_sized(ptr.as_ptr(), layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            false,
+            |layout| self.alloc_with(layout, false),
+            |ptr| self.dealloc_with(ptr, false),
+        )
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            true,
+            |layout| self.alloc_with(layout, false),
+            |ptr| self.dealloc_with(ptr, false),
+        )
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(
+            ptr,
+            old_layout,
+            new_layout,
+            false,
+            |layout| self.alloc_with(layout, false),
+            |ptr| self.dealloc_with(ptr, false),
+        )
+    }
+}
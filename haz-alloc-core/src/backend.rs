@@ -1,5 +1,6 @@
+use crate::RuntimeConfig;
 use core::cell::Cell;
-use core::ptr;
+use core::{fmt, ptr};
 
 pub struct TlsCallback {
     pub func: Cell<Option<fn()>>,
@@ -15,6 +16,13 @@ impl TlsCallback {
     }
 }
 
+impl Default for TlsCallback {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// This trait contains external functions that are provided by the user
 /// in order for the library to perform system actions such as
 /// page allocation.
@@ -72,6 +80,34 @@ pub unsafe trait Backend {
     ///
     /// The callback must be #[thread_local].
     unsafe fn tls_attach(callback: *const TlsCallback);
+
+    /// Report `args` and abort, on heap corruption.
+    ///
+    /// This must not allocate. The default implementation panics.
+    fn abort(args: fmt::Arguments<'_>) -> ! {
+        panic!("{}", args)
+    }
+
+    /// Returns the runtime configuration.
+    ///
+    /// It is called on every allocation, so it should be cached. The default
+    /// implementation returns `RuntimeConfig::DEFAULT`.
+    #[inline]
+    fn config() -> &'static RuntimeConfig {
+        &RuntimeConfig::DEFAULT
+    }
+
+    /// Fill `buf` with random bytes of the system, returning whether it
+    /// succeeded.
+    ///
+    /// It seeds the secrets of hardened mode, and must not allocate or
+    /// block. The default implementation returns `false`, leaving only the
+    /// addresses of the process to seed them.
+    #[inline]
+    fn random(buf: &mut [u8]) -> bool {
+        let _ = buf;
+        false
+    }
 }
 
 /// # Safety
@@ -87,6 +123,7 @@ pub unsafe trait Mutex: 'static + Sync + Send {
     /// 
     /// The mutex must not be moved until dropped.
     /// The mutex must not be initialized.
+    #[allow(clippy::new_ret_no_self)]
     unsafe fn new(ptr: *mut Self);
 
     /// Lock a mutex.
@@ -42,6 +42,16 @@ pub fn is_zero_range(set: &[usize], index: usize, len: usize) -> bool {
         .all(|(x, y)| x & y == 0)
 }
 
+#[inline]
+pub fn count(set: &[usize]) -> usize {
+    set.iter().map(|x| x.count_ones() as usize).sum()
+}
+
+#[inline]
+pub fn get(set: &[usize], index: usize) -> bool {
+    set[index / USIZE_BITS] & (1 << (index % USIZE_BITS)) != 0
+}
+
 #[inline]
 pub fn set(set: &mut [usize], index: usize) {
     set[index / USIZE_BITS] |= 1 << (index % USIZE_BITS);
@@ -76,8 +86,7 @@ pub fn clear_range(set: &mut [usize], index: usize, len: usize) {
 
 #[inline]
 fn mask(index: usize, len: usize) -> impl Iterator<Item = usize> {
-    iter::repeat(0)
-        .take(index / USIZE_BITS)
+    iter::repeat_n(0, index / USIZE_BITS)
         .chain(
             ((index / USIZE_BITS)..=((index + len) / USIZE_BITS)).map(move |i| {
                 let mut mask: usize = !0;
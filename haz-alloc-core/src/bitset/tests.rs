@@ -18,6 +18,18 @@ fn test_is_zero_range() {
     assert!(is_zero_range(&[1, 1], 1, USIZE_BITS - 1));
 }
 
+#[test]
+fn test_get() {
+    assert!(get(&[0, 2], USIZE_BITS + 1));
+    assert!(!get(&[0, 2], 1));
+}
+
+#[test]
+fn test_count() {
+    assert_eq!(count(&[0, 0]), 0);
+    assert_eq!(count(&[3, !0]), 2 + USIZE_BITS);
+}
+
 #[test]
 fn test_set_range() {
     let mut x = [0];
@@ -0,0 +1,90 @@
+//! Memory budget.
+//!
+//! Bytes committed through `reserve` are charged against a limit, and
+//! commits beyond it fail. When an allocation fails, the OOM handler is
+//! called, and may free memory and ask for the allocation to be retried.
+
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// Most bytes committed at once
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+// Bytes committed
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+// Address of the OOM handler, or 0 if none
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+// Most times the OOM handler is called for one allocation
+const MAX_RETRIES: usize = 4;
+
+// Set while the OOM handler of the thread runs
+#[thread_local]
+static BUSY: Cell<bool> = Cell::new(false);
+
+/// Set the most bytes committed at once, or lift the limit if `None`.
+pub(crate) fn set_limit(bytes: Option<usize>) {
+    LIMIT.store(bytes.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+pub(crate) fn set_handler(f: Option<fn(Layout) -> bool>) {
+    HANDLER.store(f.map_or(0, |f| f as usize), Ordering::Relaxed);
+}
+
+/// Returns the bytes committed.
+#[inline]
+pub(crate) fn committed() -> usize {
+    COMMITTED.load(Ordering::Relaxed)
+}
+
+/// Charge `size` bytes about to be committed, or return `false` if they do
+/// not fit in the limit.
+#[inline]
+pub(crate) fn charge(size: usize) -> bool {
+    let limit = LIMIT.load(Ordering::Relaxed);
+    if limit == usize::MAX {
+        COMMITTED.fetch_add(size, Ordering::Relaxed);
+        return true;
+    }
+    COMMITTED
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+            x.checked_add(size).filter(|&x| x <= limit)
+        })
+        .is_ok()
+}
+
+/// Give back `size` bytes charged before.
+#[inline]
+pub(crate) fn release(size: usize) {
+    COMMITTED.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Allocate with `alloc`, calling the OOM handler for `layout` and trying
+/// again while it fails, at most `MAX_RETRIES` times.
+#[inline]
+pub(crate) fn with_retries(layout: Layout, mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+    let mut ptr = alloc();
+    let mut retries = 0;
+    while ptr.is_null() && retries < MAX_RETRIES && retry(layout) {
+        ptr = alloc();
+        retries += 1;
+    }
+    ptr
+}
+
+/// Call the OOM handler after an allocation of `layout` failed, returning
+/// whether to try again.
+///
+/// The handler is not called again by the allocations it makes.
+#[cold]
+pub(crate) fn retry(layout: Layout) -> bool {
+    let f = HANDLER.load(Ordering::Relaxed);
+    if f == 0 || BUSY.replace(true) {
+        return false;
+    }
+    let f = unsafe { mem::transmute::<usize, fn(Layout) -> bool>(f) };
+    let retry = f(layout);
+    BUSY.set(false);
+    retry
+}
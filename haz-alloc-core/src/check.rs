@@ -0,0 +1,95 @@
+//! Checks of blocks being deallocated.
+//!
+//! Cheap checks are always done, catching frees of pointers that are not
+//! the start of a block, and double frees of blocks last freed to their
+//! page or still in the cache of the thread. In debug mode, the reservation
+//! of a block is also looked up in the registries before being read, and a
+//! small slot is looked up in the whole free list of its page. Slots in the
+//! cache of other threads or waiting in a remote queue are never looked up.
+//! Blocks in the quarantine are looked up if they still hold its pattern.
+
+use crate::__internal::UsizeExt;
+use crate::config::Tuned;
+use crate::reserve::{self, ReserveHeader, ReserveType};
+use crate::{huge, quarantine, subhuge};
+use core::{fmt, ptr};
+
+/// Check that `ptr` can be deallocated, aborting otherwise, and return the
+/// header of its reservation.
+///
+/// # Safety
+///
+/// Pointer must be in memory that is readable, unless in debug mode.
+#[inline]
+pub(crate) unsafe fn free<B: Tuned>(ptr: *mut u8) -> *mut ReserveHeader {
+    let header = reservation::<B>(ptr);
+    match (*header).ty {
+        ReserveType::Huge => huge::check_free::<B>(header, ptr),
+        ReserveType::SubHuge => subhuge::check_free::<B>(header, ptr),
+    }
+    if quarantine::ENABLED {
+        quarantine::check_free::<B>(ptr);
+    }
+    header
+}
+
+/// Like `free`, for a block of small class `class`, or a larger block if
+/// `None`, also returning the metadata of its page, or null if huge.
+///
+/// # Safety
+///
+/// Pointer must be in memory that is readable, unless in debug mode.
+#[inline]
+pub(crate) unsafe fn free_sized<B: Tuned>(
+    ptr: *mut u8,
+    class: Option<usize>,
+) -> (*mut ReserveHeader, *mut subhuge::Page) {
+    let header = reservation::<B>(ptr);
+    let page = match (*header).ty {
+        ReserveType::Huge => {
+            huge::check_free::<B>(header, ptr);
+            ptr::null_mut()
+        }
+        ReserveType::SubHuge => {
+            subhuge::check_free_sized::<B>(header, ptr, class.map_or(-1, |x| x as isize))
+        }
+    };
+    if quarantine::ENABLED {
+        quarantine::check_free::<B>(ptr);
+    }
+    (header, page)
+}
+
+/// Returns the header of the reservation of `ptr`, aborting if it is not
+/// one of haz-alloc.
+///
+/// # Safety
+///
+/// Pointer must be in memory that is readable, unless in debug mode.
+#[inline]
+unsafe fn reservation<B: Tuned>(ptr: *mut u8) -> *mut ReserveHeader {
+    let header = (ptr as usize).wrapping_sub(1).align_down(B::RESERVE_ALIGN) as *mut ReserveHeader;
+    if B::DEBUG && !subhuge::is_arena::<B>(header) && !huge::is_block(header) {
+        invalid_free::<B>(ptr, format_args!("not allocated by haz-alloc"));
+    }
+    if !reserve::is_valid(header) {
+        invalid_free::<B>(ptr, format_args!("not allocated by haz-alloc"));
+    }
+    header
+}
+
+#[cold]
+pub(crate) fn invalid_free<B: Tuned>(ptr: *mut u8, reason: fmt::Arguments<'_>) -> ! {
+    B::abort(format_args!(
+        "haz-alloc: invalid free of {:p}: {}",
+        ptr, reason
+    ))
+}
+
+#[cold]
+pub(crate) fn double_free<B: Tuned>(ptr: *mut u8, reason: fmt::Arguments<'_>) -> ! {
+    B::abort(format_args!(
+        "haz-alloc: double free of {:p}: {}",
+        ptr, reason
+    ))
+}
@@ -0,0 +1,75 @@
+use crate::config::{Config, DefaultConfig, MAX_CLASSES};
+
+#[cfg(test)]
+mod tests;
+
+/// A size class of small blocks.
+///
+/// Small requests are rounded up to the size of their class, which is also
+/// their usable size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SizeClass {
+    index: usize,
+    size: usize,
+}
+
+impl SizeClass {
+    /// The largest size served by a small class of `DefaultConfig`.
+    pub const MAX_SIZE: usize = {
+        let classes = DefaultConfig::SMALL_CLASSES;
+        classes[classes.len() - 1]
+    };
+
+    /// Returns the smallest class of `DefaultConfig` fitting `size`, or
+    /// `None` if `size` is larger than `MAX_SIZE`.
+    ///
+    /// Requests with an alignment are served by the class of their size
+    /// rounded up to the alignment.
+    #[inline]
+    pub fn of(size: usize) -> Option<Self> {
+        Self::of_in::<DefaultConfig>(size)
+    }
+
+    /// Like `of`, with the classes of `C`.
+    #[inline]
+    pub fn of_in<C: Config>(size: usize) -> Option<Self> {
+        let index = C::SMALL_CLASSES.partition_point(|x| *x < size);
+        C::SMALL_CLASSES
+            .get(index)
+            .map(|size| Self { index, size: *size })
+    }
+
+    /// Iterate over all classes of `DefaultConfig`, from the smallest to the
+    /// largest.
+    pub fn all() -> impl Iterator<Item = Self> {
+        Self::all_in::<DefaultConfig>()
+    }
+
+    /// Like `all`, with the classes of `C`.
+    pub fn all_in<C: Config>() -> impl Iterator<Item = Self> {
+        C::SMALL_CLASSES
+            .iter()
+            .enumerate()
+            // Some classes are duplicated depending on the platform.
+            .filter(|(index, size)| *index == 0 || C::SMALL_CLASSES[index - 1] != **size)
+            .map(|(index, size)| Self { index, size: *size })
+    }
+
+    /// Returns the size of blocks in this class.
+    #[inline]
+    pub fn size(self) -> usize {
+        self.size
+    }
+
+    /// Returns the index of this class, smaller than `SizeClass::count()`.
+    #[inline]
+    pub fn index(self) -> usize {
+        self.index
+    }
+
+    /// Returns an upper bound on the indices of the classes.
+    #[inline]
+    pub fn count() -> usize {
+        MAX_CLASSES
+    }
+}
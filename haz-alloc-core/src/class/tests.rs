@@ -0,0 +1,15 @@
+use super::*;
+
+#[test]
+fn test_of() {
+    let mut prev = 0;
+    for class in SizeClass::all() {
+        assert!(class.size() > prev);
+        assert!(class.index() < SizeClass::count());
+        assert_eq!(SizeClass::of(class.size()), Some(class));
+        assert_eq!(SizeClass::of(prev + 1), Some(class));
+        prev = class.size();
+    }
+    assert_eq!(prev, SizeClass::MAX_SIZE);
+    assert_eq!(SizeClass::of(SizeClass::MAX_SIZE + 1), None);
+}
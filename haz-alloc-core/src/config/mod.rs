@@ -0,0 +1,372 @@
+#[cfg(test)]
+mod tests;
+
+use crate::__internal::{UsizeExt, SMALL_CLASSES};
+use crate::backend::TlsCallback;
+use crate::{subhuge, Backend};
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+use core::{cmp, fmt, mem};
+
+/// Most classes of `Config::SMALL_CLASSES`.
+pub(crate) const MAX_CLASSES: usize = 48;
+
+/// Most arenas of `Config::ARENAS`.
+pub(crate) const MAX_ARENAS: usize = 64;
+
+// Smallest page size the allocator runs with
+pub(crate) const MIN_PAGESIZE: usize = 4096;
+
+// Most pages of `MIN_PAGESIZE` in the slab of a small class
+const MAX_SLAB_PAGES: usize = 8;
+
+// Sizes are looked up in the classes by multiples of this
+const GRANULE: usize = mem::size_of::<usize>();
+const LOOKUP_LEN: usize = MIN_PAGESIZE / GRANULE + 1;
+
+/// Tuning fixed at compile time, that `Alloc` and `Heap` are generic over.
+///
+/// Every constant has a default, so a config only overrides what it
+/// tunes. Constants out of range fail the build where the allocator is
+/// created. Every `Alloc` and `Heap` of a process shares the arenas, so
+/// they must all use the same config.
+pub trait Config {
+    /// Alignment and size of the reservation of an arena, a power of two
+    /// of at most 64 MiB, at least 8 times `HUGE_THRESHOLD`, and with room
+    /// for the metadata of the arena and 64 pages of blocks.
+    ///
+    /// Blocks find the header of their reservation by rounding down to it.
+    const RESERVE_ALIGN: usize = if cfg!(target_pointer_width = "64") {
+        32 * 1024 * 1024
+    } else {
+        2 * 1024 * 1024
+    };
+
+    /// Size above which blocks get a reservation of their own, at least the
+    /// largest small class. `RuntimeConfig::huge_threshold` can lower it.
+    const HUGE_THRESHOLD: usize = 256 * 1024;
+
+    /// Sizes of the small classes, ascending, at most 48 of them.
+    ///
+    /// Each is a multiple of the size of a pointer, fits a 4 KiB page, and
+    /// is a multiple of every power of two rounding up to it, so that its
+    /// slots keep the alignment of any layout it serves.
+    const SMALL_CLASSES: &'static [usize] = SMALL_CLASSES;
+
+    /// Most arenas of exited threads kept for new threads, up to 64.
+    /// `RuntimeConfig::arenas` can lower it.
+    const ARENAS: usize = 16;
+
+    /// Whether blocks are padded with redzones and filled with patterns, as
+    /// with the `debug` feature.
+    const DEBUG: bool = cfg!(feature = "debug");
+}
+
+/// The config of `Alloc` and `Heap` unless given another.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultConfig;
+
+impl Config for DefaultConfig {}
+
+/// A backend with a config, which the internals of the allocator are
+/// generic over.
+pub(crate) trait Tuned: Backend + Sized {
+    type Config: Config;
+
+    const RESERVE_ALIGN: usize = <Self::Config as Config>::RESERVE_ALIGN;
+    const HUGE_THRESHOLD: usize = <Self::Config as Config>::HUGE_THRESHOLD;
+    const SMALL_CLASSES: &'static [usize] = <Self::Config as Config>::SMALL_CLASSES;
+    const ARENAS: usize = <Self::Config as Config>::ARENAS;
+    const DEBUG: bool = <Self::Config as Config>::DEBUG;
+
+    /// Largest small block.
+    const SMALL_MAX: usize = Self::SMALL_CLASSES[Self::SMALL_CLASSES.len() - 1];
+
+    /// Class of each size up to `SMALL_MAX`, by multiples of `GRANULE`.
+    const CLASS_OF: [u8; LOOKUP_LEN] = lookup(Self::SMALL_CLASSES);
+
+    /// Pages of `MIN_PAGESIZE` in the slab of each small class.
+    const SLAB_PAGES: [u8; MAX_CLASSES] = slab_pages(Self::SMALL_CLASSES);
+
+    /// Fails the build if the config is out of range.
+    const VALID: () = {
+        validate::<Self::Config>();
+        subhuge::validate::<Self>();
+    };
+
+    /// Returns the smallest small class fitting `size`.
+    ///
+    /// Size must not be larger than `SMALL_MAX`.
+    #[inline]
+    fn class_of(size: usize) -> usize {
+        Self::CLASS_OF[size.div_ceil(GRANULE)] as usize
+    }
+
+    /// Returns the size of the slab of a small class, in whole pages.
+    #[inline]
+    fn slab_size(class: usize) -> usize {
+        (Self::SLAB_PAGES[class] as usize * MIN_PAGESIZE).align_up(Self::pagesize())
+    }
+}
+
+/// The backend `B` with the config `C`.
+pub(crate) struct With<B, C>(PhantomData<(B, C)>);
+
+unsafe impl<B: Backend, C: Config> Backend for With<B, C> {
+    type Mutex = B::Mutex;
+
+    #[inline]
+    fn mreserve(ptr: *mut u8, size: usize) -> *mut u8 {
+        B::mreserve(ptr, size)
+    }
+
+    #[inline]
+    unsafe fn mcommit(ptr: *mut u8, size: usize) -> bool {
+        B::mcommit(ptr, size)
+    }
+
+    #[inline]
+    unsafe fn mdecommit(ptr: *mut u8, size: usize) {
+        B::mdecommit(ptr, size)
+    }
+
+    #[inline]
+    unsafe fn munreserve(ptr: *mut u8, size: usize) {
+        B::munreserve(ptr, size)
+    }
+
+    #[inline]
+    fn pagesize() -> usize {
+        B::pagesize()
+    }
+
+    #[inline]
+    unsafe fn tls_attach(callback: *const TlsCallback) {
+        B::tls_attach(callback)
+    }
+
+    #[inline]
+    fn abort(args: fmt::Arguments<'_>) -> ! {
+        B::abort(args)
+    }
+
+    #[inline]
+    fn config() -> &'static RuntimeConfig {
+        B::config()
+    }
+
+    #[inline]
+    fn random(buf: &mut [u8]) -> bool {
+        B::random(buf)
+    }
+}
+
+impl<B: Backend, C: Config> Tuned for With<B, C> {
+    type Config = C;
+}
+
+const fn lookup(classes: &[usize]) -> [u8; LOOKUP_LEN] {
+    let mut table = [0; LOOKUP_LEN];
+    let (mut i, mut class) = (0, 0);
+    while i < LOOKUP_LEN {
+        while class + 1 < classes.len() && classes[class] < i * GRANULE {
+            class += 1;
+        }
+        table[i] = class as u8;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the pages of the slab of each class, the fewest wasting at most
+/// a sixteenth of the slab, or else the ones wasting the least.
+const fn slab_pages(classes: &[usize]) -> [u8; MAX_CLASSES] {
+    let mut table = [1; MAX_CLASSES];
+    let mut i = 0;
+    while i < classes.len() {
+        let size = classes[i];
+        let (mut best, mut best_waste) = (0, 0);
+        let mut pages = 1;
+        while pages <= MAX_SLAB_PAGES {
+            let slab = pages * MIN_PAGESIZE;
+            let waste = slab % size;
+            if best == 0 || waste * best < best_waste * pages {
+                (best, best_waste) = (pages, waste);
+            }
+            if waste * 16 <= slab {
+                break;
+            }
+            pages += 1;
+        }
+        table[i] = best as u8;
+        i += 1;
+    }
+    table
+}
+
+const fn validate<C: Config>() {
+    let classes = C::SMALL_CLASSES;
+    assert!(
+        !classes.is_empty() && classes.len() <= MAX_CLASSES,
+        "haz-alloc: 1 to 48 small classes are needed"
+    );
+    let mut i = 0;
+    while i < classes.len() {
+        let (size, prev) = (classes[i], if i == 0 { 0 } else { classes[i - 1] });
+        let align = 1 << size.trailing_zeros();
+        assert!(
+            size != 0 && size % GRANULE == 0 && size >= prev,
+            "haz-alloc: small classes must be ascending multiples of the pointer size"
+        );
+        assert!(
+            (prev / (align * 2) + 1) * (align * 2) > size,
+            "haz-alloc: a small class is not aligned to a layout it serves"
+        );
+        assert!(
+            size <= MIN_PAGESIZE,
+            "haz-alloc: a small class does not fit a page"
+        );
+        i += 1;
+    }
+    assert!(
+        C::HUGE_THRESHOLD >= classes[classes.len() - 1],
+        "haz-alloc: the huge threshold is below the largest small class"
+    );
+    assert!(
+        C::RESERVE_ALIGN.is_power_of_two() && C::RESERVE_ALIGN / 8 >= C::HUGE_THRESHOLD,
+        "haz-alloc: the reservation alignment is not a power of two at least 8 times the huge threshold"
+    );
+    assert!(
+        C::RESERVE_ALIGN <= 64 * 1024 * 1024,
+        "haz-alloc: the reservation alignment is above 64 MiB"
+    );
+    assert!(
+        C::ARENAS <= MAX_ARENAS,
+        "haz-alloc: more than 64 arenas in the pool"
+    );
+}
+
+/// Tuning read at runtime, supplied by `Backend::config`.
+///
+/// Options out of range are clamped where they are used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    /// Most arenas of exited threads kept for new threads, up to
+    /// `Config::ARENAS`.
+    pub arenas: usize,
+    /// Size above which blocks get a reservation of their own, between the
+    /// largest small class and `Config::HUGE_THRESHOLD`.
+    pub huge_threshold: usize,
+    /// Whether the arena of an exiting thread is released at once if it has
+    /// no block, instead of kept until `purge`.
+    pub purge_on_exit: bool,
+    /// Times a mutex of the backend spins before sleeping.
+    pub spin: u32,
+    /// Most bytes held by the quarantine, with the `quarantine` feature.
+    pub quarantine: usize,
+    /// Whether blocks never freed are reported at exit, if the backend
+    /// supports it.
+    pub leak_report: bool,
+    /// Mean bytes between allocations sampled by the heap profiler of the
+    /// backend, or `0` if disabled.
+    pub profile_rate: usize,
+}
+
+impl RuntimeConfig {
+    /// The configuration of backends not supplying their own, leaving the
+    /// arenas and the huge threshold to the `Config`.
+    pub const DEFAULT: Self = Self {
+        arenas: usize::MAX,
+        huge_threshold: usize::MAX,
+        purge_on_exit: false,
+        spin: 100,
+        quarantine: 4 * 1024 * 1024,
+        leak_report: false,
+        profile_rate: 0,
+    };
+
+    /// Apply the options of `conf`, a list of `key:value` separated by
+    /// commas, such as `arenas:4,huge_threshold:64k`.
+    ///
+    /// The keys are the names of the fields. Sizes take an optional `k`,
+    /// `m` or `g` suffix, and flags are `true`, `false`, `1` or `0`.
+    ///
+    /// Returns the first invalid option, after applying the others.
+    pub fn apply<'a>(&mut self, conf: &'a [u8]) -> Result<(), &'a [u8]> {
+        let mut result = Ok(());
+        for option in conf.split(|&c| c == b',').filter(|x| !x.is_empty()) {
+            if self.apply_option(option).is_none() && result.is_ok() {
+                result = Err(option);
+            }
+        }
+        result
+    }
+
+    fn apply_option(&mut self, option: &[u8]) -> Option<()> {
+        let i = option.iter().position(|&c| c == b':')?;
+        let (key, value) = (&option[..i], &option[i + 1..]);
+        match key {
+            b"arenas" => self.arenas = parse_size(value)?,
+            b"huge_threshold" => self.huge_threshold = parse_size(value)?,
+            b"purge_on_exit" => self.purge_on_exit = parse_flag(value)?,
+            b"spin" => self.spin = u32::try_from(parse_size(value)?).ok()?,
+            b"quarantine" => self.quarantine = parse_size(value)?,
+            b"leak_report" => self.leak_report = parse_flag(value)?,
+            b"profile_rate" => self.profile_rate = parse_size(value)?,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+impl Default for RuntimeConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+fn parse_size(value: &[u8]) -> Option<usize> {
+    let (digits, shift) = match value.last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut n: usize = 0;
+    for &c in digits {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        n = n.checked_mul(10)?.checked_add((c - b'0') as usize)?;
+    }
+    n.checked_mul(1 << shift)
+}
+
+fn parse_flag(value: &[u8]) -> Option<bool> {
+    match value {
+        b"true" | b"1" => Some(true),
+        b"false" | b"0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the most arenas kept in the pool.
+#[inline]
+pub(crate) fn arenas<B: Tuned>() -> usize {
+    cmp::min(B::config().arenas, B::ARENAS)
+}
+
+/// Returns the size above which blocks are huge.
+///
+/// Small blocks are never huge, as `dealloc_sized` finds them from their
+/// layout alone.
+#[inline]
+pub(crate) fn huge_threshold<B: Tuned>() -> usize {
+    B::config()
+        .huge_threshold
+        .clamp(B::SMALL_MAX, B::HUGE_THRESHOLD)
+}
@@ -0,0 +1,167 @@
+use super::*;
+
+#[test]
+fn test_apply() {
+    let mut config = RuntimeConfig::DEFAULT;
+    assert_eq!(
+        config.apply(b"arenas:4,huge_threshold:64k,purge_on_exit:true,spin:0,"),
+        Ok(())
+    );
+    assert_eq!(config.arenas, 4);
+    assert_eq!(config.huge_threshold, 64 * 1024);
+    assert!(config.purge_on_exit);
+    assert_eq!(config.spin, 0);
+
+    assert_eq!(
+        config.apply(b"quarantine:1M,leak_report:1,profile_rate:512K"),
+        Ok(())
+    );
+    assert_eq!(config.quarantine, 1024 * 1024);
+    assert!(config.leak_report);
+    assert_eq!(config.profile_rate, 512 * 1024);
+
+    assert_eq!(config.apply(b""), Ok(()));
+    assert_eq!(config.apply(b"leak_report:false"), Ok(()));
+    assert!(!config.leak_report);
+}
+
+#[test]
+fn test_apply_invalid() {
+    let mut config = RuntimeConfig::DEFAULT;
+    assert_eq!(
+        config.apply(b"arenas,spin:2,foo:1,arenas:x"),
+        Err(&b"arenas"[..])
+    );
+    // Valid options are still applied.
+    assert_eq!(config.spin, 2);
+    assert_eq!(config.arenas, RuntimeConfig::DEFAULT.arenas);
+
+    for conf in [
+        &b"arenas:"[..],
+        b"arenas:k",
+        b"arenas:-1",
+        b"arenas:1t",
+        b"spin:4294967296",
+        b"quarantine:99999999999999999999",
+        b"profile_rate:18446744073709551615k",
+        b"leak_report:yes",
+        b"leak_report:",
+    ] {
+        assert_eq!(config.apply(conf), Err(conf));
+    }
+    assert_eq!(
+        config,
+        RuntimeConfig {
+            spin: 2,
+            ..RuntimeConfig::DEFAULT
+        }
+    );
+}
+
+#[test]
+fn test_lookup() {
+    let table = lookup(SMALL_CLASSES);
+    for size in 1..=SMALL_CLASSES[SMALL_CLASSES.len() - 1] {
+        let class = table[size.div_ceil(GRANULE)] as usize;
+        assert_eq!(class, SMALL_CLASSES.partition_point(|x| *x < size));
+    }
+    for (class, size) in SMALL_CLASSES.iter().enumerate() {
+        // Duplicated classes are looked up as the first of them.
+        let first = SMALL_CLASSES.partition_point(|x| x < size);
+        assert_eq!(table[size / GRANULE] as usize, first);
+        assert!(class == first || SMALL_CLASSES[first] == *size);
+    }
+}
+
+#[test]
+fn test_classes() {
+    let table = lookup(SMALL_CLASSES);
+    for size in SMALL_CLASSES {
+        assert_eq!(SMALL_CLASSES[table[size / GRANULE] as usize], *size);
+    }
+}
+
+#[test]
+fn test_waste() {
+    let mut prev = 0;
+    for &size in SMALL_CLASSES {
+        if size == prev {
+            continue;
+        }
+        // The worst request is one byte more than the previous class.
+        let waste = size - prev - 1;
+        if prev < 64 {
+            assert!(waste < 16, "class {} wastes {} bytes", size, waste);
+        } else {
+            assert!(waste * 5 < size, "class {} wastes {} bytes", size, waste);
+        }
+
+        prev = size;
+    }
+    assert!(SMALL_CLASSES.len() <= MAX_CLASSES);
+}
+
+#[test]
+fn test_slab_pages() {
+    let table = slab_pages(SMALL_CLASSES);
+    for (class, &size) in SMALL_CLASSES.iter().enumerate() {
+        let pages = table[class] as usize;
+        assert!((1..=MAX_SLAB_PAGES).contains(&pages));
+
+        let slab = pages * MIN_PAGESIZE;
+        let unused = slab % size;
+        assert!(
+            unused * 16 <= slab,
+            "class {} leaves {} bytes of a slab of {} pages",
+            size,
+            unused,
+            pages
+        );
+        // Pages only hold slots, so classes dividing a page fill it.
+        if MIN_PAGESIZE.is_multiple_of(size) {
+            assert_eq!((pages, unused), (1, 0));
+        }
+    }
+}
+
+#[test]
+fn test_validate() {
+    validate::<DefaultConfig>();
+}
+
+#[test]
+#[should_panic(expected = "not aligned")]
+fn test_validate_unaligned() {
+    struct Unaligned;
+
+    impl Config for Unaligned {
+        // Layouts of 32 bytes aligned to 32 would get slots of 48 bytes.
+        const SMALL_CLASSES: &'static [usize] = &[16, 48];
+    }
+
+    validate::<Unaligned>();
+}
+
+#[test]
+#[should_panic(expected = "above 64 MiB")]
+fn test_validate_reserve_too_large() {
+    struct TooLarge;
+
+    impl Config for TooLarge {
+        const RESERVE_ALIGN: usize = 128 * 1024 * 1024;
+    }
+
+    validate::<TooLarge>();
+}
+
+#[test]
+#[should_panic(expected = "huge threshold")]
+fn test_validate_huge_threshold() {
+    struct Small;
+
+    impl Config for Small {
+        const HUGE_THRESHOLD: usize = 1024;
+    }
+
+    validate::<Small>();
+}
@@ -0,0 +1,188 @@
+//! Debug mode, enabled by `Config::DEBUG`, which defaults to the `debug`
+//! feature.
+//!
+//! Every block is padded with a redzone after the requested size, followed
+//! by a trailer holding the requested size at the end of the usable block.
+//! The redzone is checked when the block is deallocated or reallocated.
+
+use crate::alloc::{block_kind, usable_size};
+use crate::config::Tuned;
+use crate::walk::BlockKind;
+use core::alloc::Layout;
+use core::{fmt, mem};
+
+/// Fill of fresh blocks, except zeroed ones.
+const ALLOC_FILL: u8 = 0xAA;
+/// Fill of freed small slots.
+const FREE_FILL: u8 = 0xDD;
+const REDZONE_FILL: u8 = 0xFD;
+
+const REDZONE: usize = 16;
+const TRAILER: usize = mem::size_of::<usize>();
+
+/// Returns the layout actually allocated for `layout`.
+///
+/// This is `layout` itself unless the debug mode is enabled.
+#[inline]
+pub(crate) fn pad<B: Tuned>(layout: Layout) -> Option<Layout> {
+    if !B::DEBUG {
+        return Some(layout);
+    }
+    let size = layout.size().checked_add(REDZONE + TRAILER)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Fill a fresh block of `size` bytes and seal its redzone.
+///
+/// # Safety
+///
+/// Pointer must be valid and allocated with `pad` of `size`.
+pub(crate) unsafe fn on_alloc<B: Tuned>(ptr: *mut u8, size: usize, zeroed: bool) {
+    if !zeroed {
+        ptr.write_bytes(ALLOC_FILL, size);
+    }
+    seal(ptr, size, usable_size::<B>(ptr));
+}
+
+/// Fill the bytes of a block resized in place from `old_size` to `size`
+/// and seal its redzone again.
+///
+/// # Safety
+///
+/// Pointer must be valid and reallocated with `pad` of `size`.
+pub(crate) unsafe fn on_resize<B: Tuned>(ptr: *mut u8, old_size: usize, size: usize) {
+    if size > old_size {
+        ptr.add(old_size).write_bytes(ALLOC_FILL, size - old_size);
+    }
+    seal(ptr, size, usable_size::<B>(ptr));
+}
+
+/// Check a block about to be deallocated, and fill it if it is a small
+/// slot.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn on_free<B: Tuned>(ptr: *mut u8) {
+    check::<B>(ptr);
+    poison::<B>(ptr);
+}
+
+/// Like `on_free`, also checking that `layout` is the layout the block was
+/// allocated with, and returning the padded layout.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn on_free_sized<B: Tuned>(ptr: *mut u8, layout: Layout) -> Layout {
+    let size = check::<B>(ptr);
+    if size != layout.size() {
+        B::abort(format_args!(
+            "haz-alloc: block {:p} of size {} deallocated with size {}",
+            ptr,
+            size,
+            layout.size()
+        ));
+    }
+    poison::<B>(ptr);
+    // The layout was already padded when allocating.
+    pad::<B>(layout).unwrap()
+}
+
+/// Check the redzone of a block, returning its requested size.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn check<B: Tuned>(ptr: *mut u8) -> usize {
+    let usable = usable_size::<B>(ptr);
+    let size = ptr.add(usable - TRAILER).cast::<usize>().read_unaligned();
+    if size > usable - TRAILER - REDZONE {
+        corrupted::<B>(ptr, usable, usable - TRAILER, size);
+    }
+    let redzone = ptr.add(size);
+    for i in 0..usable - TRAILER - size {
+        if *redzone.add(i) != REDZONE_FILL {
+            corrupted::<B>(ptr, usable, size + i, size);
+        }
+    }
+    size
+}
+
+/// Returns the requested size of a block.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn size<B: Tuned>(ptr: *mut u8) -> usize {
+    let usable = usable_size::<B>(ptr);
+    ptr.add(usable - TRAILER).cast::<usize>().read_unaligned()
+}
+
+/// Returns the requested size of a block found by `walk` with `usable`
+/// bytes, or `usable` if the block was freed but not taken back yet.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+pub(crate) unsafe fn walked_size(ptr: *mut u8, usable: usize) -> usize {
+    let size = ptr.add(usable - TRAILER).cast::<usize>().read_unaligned();
+    if size > usable - TRAILER - REDZONE {
+        usable
+    } else {
+        size
+    }
+}
+
+unsafe fn seal(ptr: *mut u8, size: usize, usable: usize) {
+    ptr.add(size)
+        .write_bytes(REDZONE_FILL, usable - TRAILER - size);
+    ptr.add(usable - TRAILER)
+        .cast::<usize>()
+        .write_unaligned(size);
+}
+
+unsafe fn poison<B: Tuned>(ptr: *mut u8) {
+    if block_kind::<B>(ptr) == BlockKind::Small {
+        ptr.write_bytes(FREE_FILL, usable_size::<B>(ptr));
+    }
+}
+
+#[cold]
+unsafe fn corrupted<B: Tuned>(ptr: *mut u8, usable: usize, offset: usize, size: usize) -> ! {
+    B::abort(format_args!(
+        "haz-alloc: redzone of block {:p} of size {} corrupted at offset {}, in {}",
+        ptr,
+        size,
+        offset,
+        Class::of::<B>(ptr, usable)
+    ))
+}
+
+/// Display of the size class of a block.
+pub(crate) struct Class {
+    kind: BlockKind,
+    usable: usize,
+}
+
+impl Class {
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    pub(crate) unsafe fn of<B: Tuned>(ptr: *mut u8, usable: usize) -> Self {
+        Self {
+            kind: block_kind::<B>(ptr),
+            usable,
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            BlockKind::Small => write!(f, "small class {}", self.usable),
+            BlockKind::Large => write!(f, "large block of {} bytes", self.usable),
+            BlockKind::Huge => write!(f, "huge block of {} bytes", self.usable),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! Hardened mode, enabled by the `hardened` feature.
+//!
+//! Links of the free lists of small pages, and of the remote queues of
+//! arenas, are encoded with a secret of the page and the address of the
+//! slot holding them, and checked to stay in the page, or to be a slot of
+//! the arena, when decoded. Fresh slots of a page are handed out in a random
+//! order.
+
+use crate::Backend;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) const ENABLED: bool = cfg!(feature = "hardened");
+
+// Mixed into every seed, so that arenas at the same address differ
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a seed for `next`, from `Backend::random`, `addr` and the address
+/// of this module, which are randomized by the system, and a counter.
+pub(crate) fn seed<B: Backend>(addr: usize) -> u64 {
+    let mut random = [0; 8];
+    if !B::random(&mut random) {
+        random = [0; 8];
+    }
+    let mut x = u64::from_ne_bytes(random)
+        ^ addr as u64
+        ^ (&COUNTER as *const AtomicUsize as u64).rotate_left(32)
+        ^ COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    next(&mut x)
+}
+
+/// Returns the next number of the generator with `state`.
+pub(crate) fn next(state: &mut u64) -> u64 {
+    // SplitMix64
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Returns a number in `0..n`, with `n` not 0.
+pub(crate) fn below(state: &mut u64, n: usize) -> usize {
+    (next(state) % n as u64) as usize
+}
@@ -0,0 +1,302 @@
+use crate::__internal::UsizeExt;
+use crate::alloc::{is_huge, Alloc};
+use crate::backend::{Backend, Mutex};
+use crate::config::{Config, DefaultConfig, Tuned, With};
+use crate::hooks::NoHooks;
+use crate::reserve::{ReserveHeader, ReserveType};
+use crate::subhuge::{self, HeapArenas};
+use crate::{budget, check, debug, huge};
+#[cfg(feature = "allocator_api")]
+use core::alloc::{AllocError, Allocator};
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::cmp;
+use core::ptr::{self, NonNull};
+
+struct Inner<B: Tuned> {
+    lock: B::Mutex,
+    arenas: UnsafeCell<HeapArenas<B>>,
+    huge: UnsafeCell<huge::List>,
+}
+
+/// A private heap.
+///
+/// Blocks are allocated from arenas owned by the heap instead of the
+/// current thread, and every block is released at once when the heap is
+/// dropped.
+///
+/// Blocks of a heap must only be deallocated or reallocated by the same
+/// heap.
+pub struct Heap<B: Backend, C: Config = DefaultConfig> {
+    inner: NonNull<Inner<With<B, C>>>,
+}
+
+unsafe impl<B: Backend, C: Config> Send for Heap<B, C> {}
+
+unsafe impl<B: Backend, C: Config> Sync for Heap<B, C> {}
+
+impl<B: Backend, C: Config> Heap<B, C> {
+    /// Create a new `Heap`, or `None` if there is no memory for it.
+    ///
+    /// # Safety
+    ///
+    /// All `Heap::new` and `Alloc::new` must be called with the same
+    /// backend and config.
+    pub unsafe fn new() -> Option<Self> {
+        #[allow(clippy::let_unit_value)]
+        let () = <With<B, C> as Tuned>::VALID;
+
+        // The heap itself lives in its first arena.
+        let mut arenas = HeapArenas::new();
+        let inner =
+            arenas.alloc(Layout::new::<Inner<With<B, C>>>(), false) as *mut Inner<With<B, C>>;
+        if inner.is_null() {
+            arenas.delete();
+            return None;
+        }
+
+        ptr::addr_of_mut!((*inner).arenas).write(UnsafeCell::new(arenas));
+        ptr::addr_of_mut!((*inner).huge).write(UnsafeCell::new(huge::List::new()));
+        B::Mutex::new(ptr::addr_of_mut!((*inner).lock));
+        Some(Self {
+            inner: NonNull::new_unchecked(inner),
+        })
+    }
+
+    #[inline]
+    unsafe fn alloc_with(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let padded = match debug::pad::<With<B, C>>(layout) {
+            Some(x) => x,
+            None => return ptr::null_mut(),
+        };
+        let ptr = budget::with_retries(layout, || self.alloc_raw(padded, zeroed));
+        if C::DEBUG && !ptr.is_null() {
+            debug::on_alloc::<With<B, C>>(ptr, layout.size(), zeroed);
+        }
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_raw(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let inner = self.inner.as_ptr();
+        if is_huge::<With<B, C>>(layout) {
+            let ptr = huge::alloc::<With<B, C>>(layout);
+            if !ptr.is_null() {
+                let _guard = (*inner).lock.lock();
+                (*(*inner).huge.get()).push(header::<With<B, C>>(ptr));
+            }
+            ptr
+        } else {
+            let _guard = (*inner).lock.lock();
+            (*(*inner).arenas.get()).alloc(layout, zeroed)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    #[inline]
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, false)
+    }
+
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    #[inline]
+    pub unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_with(layout, true)
+    }
+
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        if Alloc::<B, NoHooks, C>::with_config().realloc_in_place(ptr, layout) {
+            return ptr;
+        }
+
+        let new = self.alloc(layout);
+        if new.is_null() {
+            return ptr::null_mut();
+        }
+        new.copy_from_nonoverlapping(ptr, cmp::min(layout.size(), self.size(ptr)));
+        self.dealloc(ptr);
+        new
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    pub unsafe fn dealloc(&self, ptr: *mut u8) {
+        check::free::<With<B, C>>(ptr);
+        if C::DEBUG {
+            debug::on_free::<With<B, C>>(ptr);
+        }
+        self.dealloc_raw(ptr)
+    }
+
+    #[inline]
+    unsafe fn dealloc_raw(&self, ptr: *mut u8) {
+        let header = header::<With<B, C>>(ptr);
+        match (*header).ty {
+            ReserveType::Huge => {
+                let inner = self.inner.as_ptr();
+                let guard = (*inner).lock.lock();
+                (*(*inner).huge.get()).remove(header);
+                drop(guard);
+                huge::dealloc::<With<B, C>>(header)
+            }
+            ReserveType::SubHuge => subhuge::dealloc::<With<B, C>>(header, ptr),
+        }
+    }
+
+    /// Deallocate using the layout of the block to skip loading its metadata
+    /// where possible.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Alignment must match of original allocation, and size must be between
+    /// the requested size and the usable size.
+    #[inline]
+    pub unsafe fn dealloc_sized(&self, ptr: *mut u8, layout: Layout) {
+        // Blocks are allocated with the padded layout in debug mode.
+        let class = debug::pad::<With<B, C>>(layout).and_then(subhuge::small_class::<With<B, C>>);
+        let (header, page) = check::free_sized::<With<B, C>>(ptr, class);
+        if C::DEBUG {
+            debug::on_free_sized::<With<B, C>>(ptr, layout);
+        }
+        match (*header).ty {
+            ReserveType::Huge => self.dealloc_raw(ptr),
+            ReserveType::SubHuge => match class {
+                Some(class) => subhuge::dealloc_small::<With<B, C>>(header, page, ptr, class),
+                None => subhuge::dealloc_large::<With<B, C>>(header, page),
+            },
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    pub unsafe fn size(&self, ptr: *mut u8) -> usize {
+        Alloc::<B, NoHooks, C>::with_config().size(ptr)
+    }
+}
+
+impl<B: Backend, C: Config> Drop for Heap<B, C> {
+    fn drop(&mut self) {
+        let inner = self.inner.as_ptr();
+        unsafe {
+            (*(*inner).huge.get()).delete::<With<B, C>>();
+            ptr::drop_in_place(ptr::addr_of_mut!((*inner).lock));
+            // This also releases the memory of `inner`.
+            let mut arenas = ptr::read((*inner).arenas.get());
+            arenas.delete();
+        }
+    }
+}
+
+#[inline]
+fn header<B: Tuned>(ptr: *mut u8) -> *mut ReserveHeader {
+    (ptr as usize - 1).align_down(B::RESERVE_ALIGN) as *mut ReserveHeader
+}
+
+unsafe impl<B: Backend, C: Config> GlobalAlloc for Heap<B, C> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_sized(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc(
+            ptr,
+            Layout::from_size_align_unchecked(new_size, layout.align()),
+        )
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+unsafe impl<B: Backend, C: Config> Allocator for Heap<B, C> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { Alloc::<B, NoHooks, C>::with_config().block(self.alloc(layout)) }
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        unsafe { Alloc::<B, NoHooks, C>::with_config().block(self.alloc_zeroed(layout)) }
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.dealloc_sized(ptr.as_ptr(), layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Alloc::<B, NoHooks, C>::with_config().resize(
+            ptr,
+            old_layout,
+            new_layout,
+            false,
+            |layout| self.alloc(layout),
+            |ptr| self.dealloc(ptr),
+        )
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Alloc::<B, NoHooks, C>::with_config().resize(
+            ptr,
+            old_layout,
+            new_layout,
+            true,
+            |layout| self.alloc(layout),
+            |ptr| self.dealloc(ptr),
+        )
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Alloc::<B, NoHooks, C>::with_config().resize(
+            ptr,
+            old_layout,
+            new_layout,
+            false,
+            |layout| self.alloc(layout),
+            |ptr| self.dealloc(ptr),
+        )
+    }
+}
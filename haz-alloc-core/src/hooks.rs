@@ -0,0 +1,113 @@
+use crate::alloc::{block_kind, usable_size};
+use crate::config::Tuned;
+use crate::debug;
+use crate::walk::BlockKind;
+use core::cell::Cell;
+
+/// Functions called by `Alloc` on each allocation, deallocation and
+/// reallocation, to collect metrics or trace events.
+///
+/// Every hook is given the usable size of the block, as returned by
+/// `Alloc::size`, so that the sizes reported for a block add up whether it
+/// is deallocated with its layout or not.
+///
+/// Hooks are not called again from within a hook on the same thread, so
+/// they may allocate. Blocks of heaps are never reported.
+pub trait AllocHooks {
+    /// Whether the hooks are called at all. When `false`, the size and kind
+    /// of blocks are not even looked up.
+    const ENABLED: bool = true;
+
+    /// Called after a block of `size` bytes is allocated at `ptr`.
+    #[inline]
+    fn alloc(ptr: *mut u8, size: usize, kind: BlockKind) {
+        let _ = (ptr, size, kind);
+    }
+
+    /// Called before the block of `size` bytes at `ptr` is deallocated.
+    #[inline]
+    fn dealloc(ptr: *mut u8, size: usize, kind: BlockKind) {
+        let _ = (ptr, size, kind);
+    }
+
+    /// Called after the block at `old` is resized to `size` bytes, in place
+    /// if `new` is `old`, or moved to `new` otherwise.
+    #[inline]
+    fn realloc(old: *mut u8, new: *mut u8, size: usize, kind: BlockKind) {
+        let _ = (old, new, size, kind);
+    }
+}
+
+/// Hooks doing nothing, the default of `Alloc`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoHooks;
+
+impl AllocHooks for NoHooks {
+    const ENABLED: bool = false;
+}
+
+// Set while a hook of the thread runs
+#[thread_local]
+static BUSY: Cell<bool> = Cell::new(false);
+
+struct Guard;
+
+impl Drop for Guard {
+    #[inline]
+    fn drop(&mut self) {
+        BUSY.set(false);
+    }
+}
+
+/// Call `f` unless a hook is running on this thread.
+#[inline]
+fn guarded(f: impl FnOnce()) {
+    if !BUSY.replace(true) {
+        let _guard = Guard;
+        f();
+    }
+}
+
+/// Returns the size of the block at `ptr` given to hooks.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+unsafe fn size<B: Tuned>(ptr: *mut u8) -> usize {
+    if B::DEBUG {
+        debug::size::<B>(ptr)
+    } else {
+        usable_size::<B>(ptr)
+    }
+}
+
+/// # Safety
+///
+/// Pointer must be null or valid.
+#[inline]
+pub(crate) unsafe fn alloc<B: Tuned, H: AllocHooks>(ptr: *mut u8) {
+    if H::ENABLED && !ptr.is_null() {
+        guarded(|| H::alloc(ptr, size::<B>(ptr), block_kind::<B>(ptr)));
+    }
+}
+
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+pub(crate) unsafe fn dealloc<B: Tuned, H: AllocHooks>(ptr: *mut u8) {
+    if H::ENABLED {
+        guarded(|| H::dealloc(ptr, size::<B>(ptr), block_kind::<B>(ptr)));
+    }
+}
+
+/// # Safety
+///
+/// `new` must be valid.
+#[inline]
+pub(crate) unsafe fn realloc<B: Tuned, H: AllocHooks>(old: *mut u8, new: *mut u8) {
+    if H::ENABLED {
+        guarded(|| H::realloc(old, new, size::<B>(new), block_kind::<B>(new)));
+    }
+}
@@ -1,8 +1,30 @@
-use crate::reserve::{self, ReserveHeader, ReserveType, RESERVE_ALIGN};
-use crate::Backend;
 use crate::__internal::UsizeExt;
+use crate::check;
+use crate::config::Tuned;
+use crate::reserve::{self, ReserveHeader, ReserveType};
+use crate::spin::SpinLock;
+use crate::walk::{BlockInfo, BlockKind};
 use core::alloc::Layout;
-use core::{mem, ptr};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{cmp, mem, ptr};
+
+// Number of live huge blocks
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// All live huge blocks, linked through `Header::all_next` and
+/// `Header::all_prev`.
+struct Registry {
+    lock: SpinLock,
+    head: UnsafeCell<*mut Header>,
+}
+
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+    lock: SpinLock::new(),
+    head: UnsafeCell::new(ptr::null_mut()),
+};
 
 #[repr(C)]
 struct Header {
@@ -11,58 +33,159 @@ struct Header {
     // These sizes include the header
     real_size: usize,
     reserve_size: usize,
+
+    // Offset of the block from the header
+    offset: usize,
+
+    // Links of the list owning the block, if any
+    next: *mut Header,
+    prev: *mut Header,
+
+    // Links of the registry
+    all_next: *mut Header,
+    all_prev: *mut Header,
+}
+
+/// An intrusive list of huge blocks.
+pub struct List {
+    head: *mut Header,
 }
 
-pub unsafe fn alloc<B: Backend>(layout: Layout) -> *mut u8 {
-    let (total_layout, offset) =
-        match Layout::from_size_align_unchecked(mem::size_of::<Header>(), RESERVE_ALIGN)
-            .extend(layout)
-        {
-            Ok(r) => r,
-            Err(_) => return ptr::null_mut(),
-        };
-    let total_size = total_layout.size().align_up(B::pagesize());
+impl List {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
 
-    if total_layout.align() > RESERVE_ALIGN {
-        return ptr::null_mut();
+    /// # Safety
+    ///
+    /// Header must be valid and not in a list.
+    pub unsafe fn push(&mut self, header: *mut ReserveHeader) {
+        let header = header as *mut Header;
+        (*header).prev = ptr::null_mut();
+        (*header).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = header;
+        }
+        self.head = header;
     }
 
-    let (reserve_size, header) = reserve::new::<B>(total_size, ReserveType::Huge);
+    /// # Safety
+    ///
+    /// Header must be valid and in this list.
+    pub unsafe fn remove(&mut self, header: *mut ReserveHeader) {
+        let header = header as *mut Header;
+        let (prev, next) = ((*header).prev, (*header).next);
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+        if !prev.is_null() {
+            (*prev).next = next;
+        } else {
+            self.head = next;
+        }
+    }
+
+    /// Deallocate all blocks in the list.
+    ///
+    /// # Safety
+    ///
+    /// Headers must be valid.
+    pub unsafe fn delete<B: Tuned>(&mut self) {
+        while !self.head.is_null() {
+            let header = self.head;
+            self.head = (*header).next;
+            dealloc::<B>(header as *mut ReserveHeader);
+        }
+    }
+}
+
+/// Returns the offset of the block from the header and the page-rounded
+/// size from the header to the end of the block.
+///
+/// Blocks aligned to more than `RESERVE_ALIGN` start exactly
+/// `RESERVE_ALIGN` bytes after the header, so that rounding the pointer
+/// down still finds the header.
+#[inline]
+fn block<B: Tuned>(layout: Layout) -> Option<(usize, usize)> {
+    let offset = cmp::min(
+        mem::size_of::<Header>().align_up(layout.align()),
+        B::RESERVE_ALIGN,
+    );
+    let total_size = offset
+        .checked_add(layout.size())?
+        .checked_add(B::pagesize() - 1)?
+        .align_down(B::pagesize());
+    Some((offset, total_size))
+}
+
+pub unsafe fn alloc<B: Tuned>(layout: Layout) -> *mut u8 {
+    let (offset, total_size) = match block::<B>(layout) {
+        Some(r) => r,
+        None => return ptr::null_mut(),
+    };
+
+    let (reserve_size, header) = reserve::new::<B>(
+        total_size,
+        cmp::max(layout.align(), B::RESERVE_ALIGN),
+        ReserveType::Huge,
+    );
     if header.is_null() {
         return ptr::null_mut();
     }
 
+    // The first page is commited by `reserve::new`, and anything between it
+    // and the block is never touched.
     let header = header as *mut Header;
-    B::mcommit(header as *mut u8, total_size);
+    let commit = cmp::max(B::pagesize(), offset.align_down(B::pagesize()));
+    if commit < total_size
+        && !reserve::commit::<B>(
+            header as *mut ReserveHeader,
+            (header as *mut u8).add(commit),
+            total_size - commit,
+        )
+    {
+        reserve::delete::<B>(header as *mut ReserveHeader);
+        return ptr::null_mut();
+    }
 
     ptr::addr_of_mut!((*header).real_size).write(total_size);
     ptr::addr_of_mut!((*header).reserve_size).write(reserve_size);
+    ptr::addr_of_mut!((*header).next).write(ptr::null_mut());
+    ptr::addr_of_mut!((*header).prev).write(ptr::null_mut());
+    ptr::addr_of_mut!((*header).offset).write(offset);
+    register(header);
+    LIVE.fetch_add(1, Ordering::Relaxed);
 
     (header as *mut u8).add(offset)
 }
 
-pub unsafe fn realloc_in_place<B: Backend>(
-    header: *mut ReserveHeader,
-    ptr: *mut u8,
-    layout: Layout,
-) -> bool {
-    let (total_layout, _) =
-        match Layout::from_size_align_unchecked(mem::size_of::<Header>(), RESERVE_ALIGN)
-            .extend(layout)
-        {
-            Ok(r) => r,
-            Err(_) => return false,
-        };
-    let total_size = total_layout.size().align_up(B::pagesize());
+pub unsafe fn realloc_in_place<B: Tuned>(header: *mut ReserveHeader, layout: Layout) -> bool {
+    let (_, total_size) = match block::<B>(layout) {
+        Some(r) => r,
+        None => return false,
+    };
 
     let header = header as *mut Header;
-    if total_size <= (*header).real_size {
-        let decommit = ptr.add(total_size);
-        B::mdecommit(decommit, (*header).real_size - total_size);
+    let real_size = (*header).real_size;
+    if total_size <= real_size {
+        if total_size < real_size {
+            reserve::decommit::<B>(
+                header as *mut ReserveHeader,
+                (header as *mut u8).add(total_size),
+                real_size - total_size,
+            );
+        }
         (*header).real_size = total_size;
         true
-    } else if total_size <= (*header).reserve_size {
-        B::mcommit(ptr, total_size);
+    } else if total_size <= (*header).reserve_size
+        && reserve::commit::<B>(
+            header as *mut ReserveHeader,
+            (header as *mut u8).add(real_size),
+            total_size - real_size,
+        )
+    {
         (*header).real_size = total_size;
         true
     } else {
@@ -70,9 +193,98 @@ pub unsafe fn realloc_in_place<B: Backend>(
     }
 }
 
-pub unsafe fn dealloc<B: Backend>(header: *mut ReserveHeader) {
+pub unsafe fn dealloc<B: Tuned>(header: *mut ReserveHeader) {
     let header = header as *mut Header;
+    unregister(header);
     reserve::delete::<B>(ptr::addr_of_mut!((*header).r));
+    LIVE.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// # Safety
+///
+/// Header must be valid and not registered.
+unsafe fn register(header: *mut Header) {
+    let _guard = REGISTRY.lock.lock();
+    let head = *REGISTRY.head.get();
+    (*header).all_prev = ptr::null_mut();
+    (*header).all_next = head;
+    if !head.is_null() {
+        (*head).all_prev = header;
+    }
+    *REGISTRY.head.get() = header;
+}
+
+/// # Safety
+///
+/// Header must be valid and registered.
+unsafe fn unregister(header: *mut Header) {
+    let _guard = REGISTRY.lock.lock();
+    let (prev, next) = ((*header).all_prev, (*header).all_next);
+    if !next.is_null() {
+        (*next).all_prev = prev;
+    }
+    if !prev.is_null() {
+        (*prev).all_next = next;
+    } else {
+        *REGISTRY.head.get() = next;
+    }
+}
+
+/// Call `f` with each live huge block.
+pub fn walk(f: &mut impl FnMut(&BlockInfo)) {
+    let _guard = REGISTRY.lock.lock();
+    let mut header = unsafe { *REGISTRY.head.get() };
+    while !header.is_null() {
+        unsafe {
+            let offset = (*header).offset;
+            f(&BlockInfo {
+                ptr: (header as *mut u8).add(offset),
+                size: (*header).real_size - offset,
+                kind: BlockKind::Huge,
+                arena: ptr::null(),
+            });
+            header = (*header).all_next;
+        }
+    }
+}
+
+/// Returns whether `header` is the header of a live huge block.
+pub fn is_block(header: *mut ReserveHeader) -> bool {
+    let _guard = REGISTRY.lock.lock();
+    let mut x = unsafe { *REGISTRY.head.get() };
+    while !x.is_null() {
+        if x as *mut ReserveHeader == header {
+            return true;
+        }
+        x = unsafe { (*x).all_next };
+    }
+    false
+}
+
+/// Abort unless `ptr` is the start of the huge block of `header`.
+///
+/// # Safety
+///
+/// Header must be valid.
+#[inline]
+pub unsafe fn check_free<B: Tuned>(header: *mut ReserveHeader, ptr: *mut u8) {
+    let header = header as *mut Header;
+    if ptr as usize != header as usize + (*header).offset {
+        check::invalid_free::<B>(ptr, format_args!("not the start of a huge block"));
+    }
+}
+
+/// Returns the number of live huge blocks.
+#[inline]
+pub fn live() -> usize {
+    LIVE.load(Ordering::Relaxed)
+}
+
+pub fn good_size<B: Tuned>(layout: Layout) -> usize {
+    match block::<B>(layout) {
+        Some((offset, total_size)) => total_size - offset,
+        None => layout.size(),
+    }
 }
 
 pub unsafe fn size(header: *mut ReserveHeader, ptr: *mut u8) -> usize {
@@ -0,0 +1,137 @@
+use crate::config::MAX_CLASSES;
+use crate::walk::{BlockInfo, BlockKind};
+use core::fmt::{self, Write};
+use core::ptr;
+
+/// Live blocks of an arena, or huge blocks if the arena is null.
+struct Group {
+    arena: *const u8,
+    small: [usize; MAX_CLASSES],
+    large: (usize, usize),
+    huge: (usize, usize),
+}
+
+impl Group {
+    fn new(arena: *const u8) -> Self {
+        Self {
+            arena,
+            small: [0; MAX_CLASSES],
+            large: (0, 0),
+            huge: (0, 0),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.small.iter().all(|x| *x == 0) && self.large.0 == 0 && self.huge.0 == 0
+    }
+}
+
+/// Report of live blocks, grouped by arena and size class.
+///
+/// Blocks must be added arena by arena, as `walk` does.
+pub struct Report<'a, W: Write> {
+    w: &'a mut W,
+    classes: &'static [usize],
+    group: Group,
+    blocks: usize,
+    bytes: usize,
+    started: bool,
+    result: fmt::Result,
+}
+
+impl<'a, W: Write> Report<'a, W> {
+    /// Returns a report of blocks in the small classes `classes`.
+    pub fn new(w: &'a mut W, classes: &'static [usize]) -> Self {
+        Self {
+            w,
+            classes,
+            group: Group::new(ptr::null()),
+            blocks: 0,
+            bytes: 0,
+            started: false,
+            result: Ok(()),
+        }
+    }
+
+    pub fn add(&mut self, info: &BlockInfo) {
+        if info.arena != self.group.arena {
+            self.flush();
+            self.group = Group::new(info.arena);
+        }
+
+        match info.kind {
+            // The size of small blocks is the size of their class.
+            BlockKind::Small => {
+                let class = self.classes.partition_point(|x| *x < info.size);
+                self.group.small[class] += 1;
+            }
+            BlockKind::Large => {
+                self.group.large.0 += 1;
+                self.group.large.1 += info.size;
+            }
+            BlockKind::Huge => {
+                self.group.huge.0 += 1;
+                self.group.huge.1 += info.size;
+            }
+        }
+        self.blocks += 1;
+        self.bytes += info.size;
+    }
+
+    /// Write the total, returning the number of live blocks.
+    pub fn finish(mut self) -> Result<usize, fmt::Error> {
+        self.flush();
+        let (blocks, bytes) = (self.blocks, self.bytes);
+        if blocks > 0 {
+            self.write(format_args!("total: {} blocks, {} bytes\n", blocks, bytes));
+        }
+        self.result.map(|_| self.blocks)
+    }
+
+    fn write(&mut self, args: fmt::Arguments<'_>) {
+        if self.result.is_ok() {
+            self.result = self.w.write_fmt(args);
+        }
+    }
+
+    /// Write the current group.
+    fn flush(&mut self) {
+        if self.group.is_empty() {
+            return;
+        }
+        let Group {
+            arena,
+            small,
+            large,
+            huge,
+        } = self.group;
+
+        if !self.started {
+            self.started = true;
+            self.write(format_args!("haz-alloc: leak report\n"));
+        }
+        if arena.is_null() {
+            self.write(format_args!("huge: {} blocks, {} bytes\n", huge.0, huge.1));
+            return;
+        }
+
+        self.write(format_args!("arena {:p}:\n", arena));
+        for (class, n) in small.iter().enumerate() {
+            if *n > 0 {
+                let size = self.classes[class];
+                self.write(format_args!(
+                    "  small {}: {} blocks, {} bytes\n",
+                    size,
+                    n,
+                    n * size
+                ));
+            }
+        }
+        if large.0 > 0 {
+            self.write(format_args!(
+                "  large: {} blocks, {} bytes\n",
+                large.0, large.1
+            ));
+        }
+    }
+}
@@ -1,16 +1,36 @@
 #![no_std]
 #![feature(thread_local)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 #![warn(clippy::all)]
 
 mod alloc;
 pub mod backend;
 mod bitset;
+mod budget;
+mod check;
+mod class;
+mod config;
+mod debug;
+mod hardened;
+mod heap;
+mod hooks;
 mod huge;
+mod leak;
+mod quarantine;
 mod reserve;
+mod spin;
+mod stats;
 mod subhuge;
+mod walk;
 
 #[doc(hidden)]
 pub mod __internal;
 
 pub use self::alloc::*;
 pub use self::backend::Backend;
+pub use self::class::SizeClass;
+pub use self::config::{Config, DefaultConfig, RuntimeConfig};
+pub use self::heap::Heap;
+pub use self::hooks::{AllocHooks, NoHooks};
+pub use self::stats::{ArenaStats, Stats};
+pub use self::walk::{BlockInfo, BlockKind};
@@ -0,0 +1,163 @@
+//! Quarantine of freed blocks, enabled by the `quarantine` feature.
+//!
+//! Small and large blocks are filled with a pattern when freed, and held in
+//! a FIFO until it grows past its size. The pattern is checked when they
+//! leave it, to catch writes after free.
+
+use crate::alloc::{block_kind, dealloc_raw, usable_size};
+use crate::check;
+use crate::config::Tuned;
+use crate::debug::Class;
+use crate::spin::SpinLock;
+use crate::walk::BlockKind;
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) const ENABLED: bool = cfg!(feature = "quarantine");
+
+const FILL: u8 = 0xDD;
+
+// Most blocks held at once, regardless of their size
+const CAPACITY: usize = 4096;
+
+struct Quarantine {
+    lock: SpinLock,
+    blocks: UnsafeCell<[*mut u8; CAPACITY]>,
+    // Index of the oldest block
+    head: UnsafeCell<usize>,
+    len: UnsafeCell<usize>,
+    // Usable bytes of the blocks held
+    bytes: UnsafeCell<usize>,
+}
+
+unsafe impl Sync for Quarantine {}
+
+static QUARANTINE: Quarantine = Quarantine {
+    lock: SpinLock::new(),
+    blocks: UnsafeCell::new([ptr::null_mut(); CAPACITY]),
+    head: UnsafeCell::new(0),
+    len: UnsafeCell::new(0),
+    bytes: UnsafeCell::new(0),
+};
+
+// Most bytes held at once, or `usize::MAX` to read it from the config
+static SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Set the most bytes held at once, overriding the config. Blocks over it
+/// leave as others are freed.
+#[cfg(feature = "quarantine")]
+pub(crate) fn set_size(bytes: usize) {
+    SIZE.store(bytes.min(usize::MAX - 1), Ordering::Relaxed);
+}
+
+/// Put a block being freed in the quarantine, releasing the oldest ones to
+/// make room, or return `false` if the block cannot be held.
+///
+/// # Safety
+///
+/// Pointer must be valid, and not of a heap.
+pub(crate) unsafe fn push<B: Tuned>(ptr: *mut u8) -> bool {
+    let size = match SIZE.load(Ordering::Relaxed) {
+        usize::MAX => B::config().quarantine,
+        size => size,
+    };
+    let usable = usable_size::<B>(ptr);
+    if block_kind::<B>(ptr) == BlockKind::Huge || usable > size {
+        return false;
+    }
+
+    ptr.write_bytes(FILL, usable);
+
+    let q = &QUARANTINE;
+    loop {
+        let guard = q.lock.lock();
+        let (head, len, bytes) = (*q.head.get(), *q.len.get(), *q.bytes.get());
+        if len < CAPACITY && bytes + usable <= size {
+            (*q.blocks.get())[(head + len) % CAPACITY] = ptr;
+            *q.len.get() = len + 1;
+            *q.bytes.get() = bytes + usable;
+            return true;
+        }
+
+        let oldest = pop::<B>();
+        drop(guard);
+        release::<B>(oldest);
+    }
+}
+
+/// Abort if `ptr` is held.
+///
+/// Only blocks starting with the pattern are looked up, unless in debug
+/// mode.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+pub(crate) unsafe fn check_free<B: Tuned>(ptr: *mut u8) {
+    if (B::DEBUG || (ptr as *const [u8; 8]).read() == [FILL; 8]) && contains(ptr) {
+        check::double_free::<B>(ptr, format_args!("block is in the quarantine"));
+    }
+}
+
+/// Release every block of the quarantine.
+pub(crate) fn drain<B: Tuned>() {
+    if !ENABLED {
+        return;
+    }
+    loop {
+        let guard = QUARANTINE.lock.lock();
+        if unsafe { *QUARANTINE.len.get() } == 0 {
+            return;
+        }
+        let oldest = unsafe { pop::<B>() };
+        drop(guard);
+        unsafe { release::<B>(oldest) };
+    }
+}
+
+/// Take the oldest block out.
+///
+/// # Safety
+///
+/// Lock must be locked, and the quarantine must not be empty.
+unsafe fn pop<B: Tuned>() -> *mut u8 {
+    let q = &QUARANTINE;
+    let head = *q.head.get();
+    let ptr = (*q.blocks.get())[head];
+    *q.head.get() = (head + 1) % CAPACITY;
+    *q.len.get() -= 1;
+    *q.bytes.get() -= usable_size::<B>(ptr);
+    ptr
+}
+
+/// Check that a block leaving the quarantine was not written, and free it.
+///
+/// # Safety
+///
+/// Pointer must be a block taken out of the quarantine.
+unsafe fn release<B: Tuned>(ptr: *mut u8) {
+    let usable = usable_size::<B>(ptr);
+    for i in 0..usable {
+        if *ptr.add(i) != FILL {
+            B::abort(format_args!(
+                "haz-alloc: block {:p} written at offset {} after free, in {}",
+                ptr,
+                i,
+                Class::of::<B>(ptr, usable)
+            ));
+        }
+    }
+    dealloc_raw::<B>(ptr);
+}
+
+/// Returns whether `ptr` is held.
+fn contains(ptr: *mut u8) -> bool {
+    let q = &QUARANTINE;
+    let _guard = q.lock.lock();
+    unsafe {
+        let (head, len) = (*q.head.get(), *q.len.get());
+        (0..len).any(|i| (*q.blocks.get())[(head + i) % CAPACITY] == ptr)
+    }
+}
@@ -1,10 +1,8 @@
-use crate::Backend;
+use crate::budget;
+use crate::config::Tuned;
+use core::convert::TryFrom;
 use core::ptr;
-
-#[cfg(target_pointer_width = "64")]
-pub const RESERVE_ALIGN: usize = 32 * 1024 * 1024;
-#[cfg(not(target_pointer_width = "64"))]
-pub const RESERVE_ALIGN: usize = 2 * 1024 * 1024;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[repr(u32)]
 pub enum ReserveType {
@@ -12,41 +10,125 @@ pub enum ReserveType {
     Huge,
 }
 
+// Bytes of address space currently reserved
+static RESERVED: AtomicUsize = AtomicUsize::new(0);
+
+// Written in every header, to tell it apart from other memory
+const MAGIC: u32 = 0x687a_6172;
+
 pub struct ReserveHeader {
     offset: u32,
+    magic: u32,
     size: usize,
+    // Bytes committed with `commit`, including the header page
+    committed: AtomicUsize,
     pub ty: ReserveType,
 }
 
-pub fn new<B: Backend>(size: usize, ty: ReserveType) -> (usize, *mut ReserveHeader) {
-    if size >= usize::MAX - RESERVE_ALIGN {
-        return (0, ptr::null_mut());
-    }
+/// Reserve `size` bytes starting at a header aligned to `RESERVE_ALIGN`.
+///
+/// `align` must be a power of two not smaller than `RESERVE_ALIGN`. The
+/// header is placed `RESERVE_ALIGN` bytes before a multiple of `align`, so
+/// that a block starting right after the header's `RESERVE_ALIGN` granule
+/// is aligned to `align` and still finds the header by rounding down.
+pub fn new<B: Tuned>(size: usize, align: usize, ty: ReserveType) -> (usize, *mut ReserveHeader) {
+    debug_assert!(align >= B::RESERVE_ALIGN && align.is_power_of_two());
 
-    let total_size = RESERVE_ALIGN + size;
+    let total_size = match size.checked_add(align) {
+        Some(x) => x,
+        None => return (0, ptr::null_mut()),
+    };
     let base = B::mreserve(ptr::null_mut(), total_size);
     if base.is_null() {
         return (0, ptr::null_mut());
     }
 
-    let offset = base.align_offset(RESERVE_ALIGN);
+    let offset = unsafe { base.add(B::RESERVE_ALIGN) }.align_offset(align);
+    let offset32 = match u32::try_from(offset) {
+        Ok(x) => x,
+        Err(_) => {
+            unsafe { B::munreserve(base, total_size) };
+            return (0, ptr::null_mut());
+        }
+    };
     let ptr = unsafe { base.add(offset) as *mut ReserveHeader };
+    if !budget::charge(B::pagesize()) {
+        unsafe { B::munreserve(base, total_size) };
+        return (0, ptr::null_mut());
+    }
     if unsafe { !B::mcommit(ptr as *mut u8, B::pagesize()) } {
+        budget::release(B::pagesize());
+        unsafe { B::munreserve(base, total_size) };
         return (0, ptr::null_mut());
     }
     unsafe {
         ptr.write(ReserveHeader {
-            offset: offset as u32,
+            offset: offset32,
+            magic: MAGIC,
             size: total_size,
+            committed: AtomicUsize::new(B::pagesize()),
             ty,
         });
     }
+    RESERVED.fetch_add(total_size, Ordering::Relaxed);
 
     (total_size - offset, ptr)
 }
 
+/// Returns whether `ptr` holds a header created by `new`.
+///
+/// # Safety
+///
+/// Pointer must be readable.
 #[inline]
-pub unsafe fn delete<B: Backend>(ptr: *mut ReserveHeader) {
+pub unsafe fn is_valid(ptr: *const ReserveHeader) -> bool {
+    // The type is read as an integer, as it may not be a valid variant.
+    let ty = *(ptr::addr_of!((*ptr).ty) as *const u32);
+    (*ptr).magic == MAGIC && ty <= ReserveType::Huge as u32
+}
+
+/// Commit `size` bytes at `ptr`, in the reservation of `header`, if they
+/// fit in the memory limit.
+///
+/// # Safety
+///
+/// Header must be valid, and the memory must be reserved by it.
+#[inline]
+pub unsafe fn commit<B: Tuned>(header: *const ReserveHeader, ptr: *mut u8, size: usize) -> bool {
+    if !budget::charge(size) {
+        return false;
+    }
+    if !B::mcommit(ptr, size) {
+        budget::release(size);
+        return false;
+    }
+    (*header).committed.fetch_add(size, Ordering::Relaxed);
+    true
+}
+
+/// Decommit `size` bytes at `ptr`, in the reservation of `header`.
+///
+/// # Safety
+///
+/// Header must be valid, and the memory must be committed by `commit`.
+#[inline]
+pub unsafe fn decommit<B: Tuned>(header: *const ReserveHeader, ptr: *mut u8, size: usize) {
+    B::mdecommit(ptr, size);
+    (*header).committed.fetch_sub(size, Ordering::Relaxed);
+    budget::release(size);
+}
+
+#[inline]
+pub unsafe fn delete<B: Tuned>(ptr: *mut ReserveHeader) {
     let offset = (*ptr).offset;
-    B::munreserve((ptr as *mut u8).sub(offset as usize), (*ptr).size);
+    let size = (*ptr).size;
+    budget::release((*ptr).committed.load(Ordering::Relaxed));
+    B::munreserve((ptr as *mut u8).sub(offset as usize), size);
+    RESERVED.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Returns the bytes of address space currently reserved.
+#[inline]
+pub fn reserved() -> usize {
+    RESERVED.load(Ordering::Relaxed)
 }
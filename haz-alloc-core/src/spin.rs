@@ -0,0 +1,43 @@
+use core::hint;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A lock for global state, which cannot use the backend mutex since it
+/// must be initialized in place.
+///
+/// Only meant for rarely taken locks.
+pub struct SpinLock {
+    locked: AtomicBool,
+}
+
+pub struct SpinGuard<'a> {
+    lock: &'a SpinLock,
+}
+
+impl SpinLock {
+    pub const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn lock(&self) -> SpinGuard<'_> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+        SpinGuard { lock: self }
+    }
+}
+
+impl Drop for SpinGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
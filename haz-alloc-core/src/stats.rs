@@ -0,0 +1,50 @@
+use crate::config::MAX_CLASSES;
+
+/// Statistics of the allocator, aggregated over all arenas.
+///
+/// Counters are updated as blocks are allocated and freed, so they may be
+/// slightly out of sync with each other when read while other threads
+/// allocate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes allocated in small blocks, indexed by `SizeClass::index`, for
+    /// the classes of the config.
+    pub small: [usize; MAX_CLASSES],
+    /// Number of live large blocks.
+    pub large: usize,
+    /// Number of live huge blocks.
+    pub huge: usize,
+    /// Number of pages commited in arenas.
+    pub committed_pages: usize,
+    /// Bytes committed in arenas and huge blocks, counted against the
+    /// memory limit.
+    pub committed: usize,
+    /// Bytes of address space reserved, including huge blocks.
+    pub reserved: usize,
+    /// Number of arenas used by threads or heaps.
+    pub arenas_in_use: usize,
+    /// Number of arenas parked in the pool, waiting for a thread.
+    pub arenas_parked: usize,
+}
+
+impl Stats {
+    /// Returns the total bytes allocated in small blocks.
+    #[inline]
+    pub fn small_total(&self) -> usize {
+        self.small.iter().sum()
+    }
+}
+
+/// Statistics of a single arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Bytes allocated in small blocks, indexed by `SizeClass::index`, for
+    /// the classes of the config.
+    pub small: [usize; MAX_CLASSES],
+    /// Number of live large blocks.
+    pub large: usize,
+    /// Number of pages commited in the arena.
+    pub committed_pages: usize,
+    /// Whether the arena is parked in the pool.
+    pub parked: bool,
+}
@@ -1,16 +1,19 @@
 use super::Arena;
 use crate::__internal::UsizeExt;
 use crate::backend::Mutex;
-use crate::{bitset, Backend};
+use crate::check;
+use crate::config::Tuned;
+use crate::walk::{BlockInfo, BlockKind};
+use crate::{bitset, reserve};
 use core::alloc::Layout;
 use core::cmp::Ordering;
-use core::{mem, ptr};
+use core::ptr;
 
 #[repr(C)]
 struct Page {
     p: super::Page,
 
-    // Includes header size
+    // Size of the block, in whole pages
     real_size: usize,
 }
 
@@ -19,9 +22,10 @@ struct Page {
 /// Pointer must be valid.
 ///
 /// Lock must be locked.
-pub(super) unsafe fn alloc<B: Backend>(arena: &Arena<B>, layout: Layout) -> *mut u8 {
-    let total_size =
-        (mem::size_of::<Page>().align_up(layout.align()) + layout.size()).align_up(B::pagesize());
+pub(super) unsafe fn alloc<B: Tuned>(arena: &Arena<B>, layout: Layout) -> *mut u8 {
+    // Blocks aligned to more than a page are huge, so every block starts
+    // at its first page.
+    let total_size = layout.size().align_up(B::pagesize());
     let pages = total_size / B::pagesize();
 
     let index = if let Some(x) = bitset::find_zero_run(&*arena.commited(), pages) {
@@ -30,20 +34,22 @@ pub(super) unsafe fn alloc<B: Backend>(arena: &Arena<B>, layout: Layout) -> *mut
         return ptr::null_mut();
     };
 
-    let p = (arena as *const Arena<B> as *const u8).add(index * B::pagesize()) as *mut Page;
-
-    if !B::mcommit(p as _, total_size) {
+    let block = (arena as *const Arena<B> as *mut u8).add(index * B::pagesize());
+    if !Arena::commit_meta(arena, index, pages)
+        || !reserve::commit::<B>(Arena::header(arena), block, total_size)
+    {
         return ptr::null_mut();
     }
 
     bitset::set_range(&mut *arena.commited(), index, pages);
+    Arena::map(arena, index, pages);
     *arena.rc.get() += 1;
+    *arena.large.get() += 1;
 
-    ptr::addr_of_mut!((*p).p.class).write(-1);
-    ptr::addr_of_mut!((*p).real_size).write(total_size);
-
-    let p = p.add(1) as *mut u8;
-    p.add(p.align_offset(layout.align()))
+    let p = Arena::page(arena, index) as *mut Page;
+    (*p).p.class = -1;
+    (*p).real_size = total_size;
+    block
 }
 
 /// # Safety
@@ -51,71 +57,76 @@ pub(super) unsafe fn alloc<B: Backend>(arena: &Arena<B>, layout: Layout) -> *mut
 /// Pointers must be valid.
 ///
 /// Lock must be unlocked.
-pub(super) unsafe fn realloc_in_place<B: Backend>(
+pub(super) unsafe fn realloc_in_place<B: Tuned>(
     page: *mut super::Page,
     arena: &Arena<B>,
     layout: Layout,
 ) -> bool {
     let page = page as *mut Page;
-    let total_size =
-        (mem::size_of::<Page>().align_up(layout.align()) + layout.size()).align_up(B::pagesize());
+    let total_size = layout.size().align_up(B::pagesize());
     let pages = total_size / B::pagesize();
     let old_pages = (*page).real_size / B::pagesize();
 
     match pages.cmp(&old_pages) {
         Ordering::Greater => {
-            let _ = arena.lock.lock();
+            let _guard = arena.lock.lock();
             grow_in_place(page, arena, pages, old_pages, total_size)
         }
         Ordering::Equal => true,
         Ordering::Less => {
-            let _ = arena.lock.lock();
+            let _guard = arena.lock.lock();
             shrink_in_place(page, arena, pages, old_pages, total_size)
         }
     }
 }
 
 /// Lock must be locked.
-unsafe fn grow_in_place<B: Backend>(
+unsafe fn grow_in_place<B: Tuned>(
     page: *mut Page,
     arena: &Arena<B>,
     pages: usize,
     old_pages: usize,
     total_size: usize,
 ) -> bool {
-    let index =
-        (page as usize - arena as *const Arena<B> as usize) / B::pagesize() + old_pages;
+    let first = Arena::<B>::index_of(&(*page).p);
+    let index = first + old_pages;
     let len = pages - old_pages;
 
     if !bitset::is_zero_range(&*arena.commited(), index, len) {
         return false;
     }
-    if !B::mcommit(
-        (page as *mut u8).add((*page).real_size),
-        total_size - (*page).real_size,
-    ) {
+    if !Arena::commit_meta(arena, index, len)
+        || !reserve::commit::<B>(
+            Arena::header(arena),
+            Arena::<B>::page_addr(&(*page).p).add((*page).real_size),
+            total_size - (*page).real_size,
+        )
+    {
         return false;
     }
     bitset::set_range(&mut *arena.commited(), index, len);
+    Arena::map(arena, first, pages);
     (*page).real_size = total_size;
 
     true
 }
 
 /// Lock must be locked.
-unsafe fn shrink_in_place<B: Backend>(
+unsafe fn shrink_in_place<B: Tuned>(
     page: *mut Page,
     arena: &Arena<B>,
     pages: usize,
     old_pages: usize,
     total_size: usize,
 ) -> bool {
-    let index = (page as usize - arena as *const Arena<B> as usize) / B::pagesize() + pages;
+    let index = Arena::<B>::index_of(&(*page).p) + pages;
     let len = old_pages - pages;
 
     bitset::clear_range(&mut *arena.commited(), index, len);
-    B::mdecommit(
-        (page as *mut u8).add(total_size),
+    Arena::unmap(arena, index, len);
+    reserve::decommit::<B>(
+        Arena::header(arena),
+        Arena::<B>::page_addr(&(*page).p).add(total_size),
         (*page).real_size - total_size,
     );
     (*page).real_size = total_size;
@@ -128,21 +139,66 @@ unsafe fn shrink_in_place<B: Backend>(
 /// Pointers must be valid.
 ///
 /// Lock must be unlocked.
-pub(super) unsafe fn dealloc<B: Backend>(page: *mut super::Page, arena: *const Arena<B>) {
+pub(super) unsafe fn dealloc<B: Tuned>(page: *mut super::Page, arena: *const Arena<B>) {
     let page = page as *mut Page;
 
-    let index = (page as usize - arena as usize) / B::pagesize();
+    let index = Arena::<B>::index_of(&(*page).p);
     let len = (*page).real_size / B::pagesize();
 
-    B::mdecommit(page as _, (*page).real_size);
+    // Decommit with the lock held, so that walking the arena never reads
+    // a page that is decommited but still marked as commited.
     let guard = (*arena).lock.lock();
+    let block = Arena::<B>::page_addr(&(*page).p);
+    reserve::decommit::<B>(Arena::header(arena), block, (*page).real_size);
     bitset::clear_range(&mut *(*arena).commited(), index, len);
+    Arena::unmap(arena, index + 1, len - 1);
+    (*page).p.class = super::FREED;
+    *(*arena).large.get() -= 1;
     Arena::release(arena, guard);
 }
 
+/// Call `f` with the block of a large page, returning the number of pages
+/// of the block.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+///
+/// Lock must be locked.
+pub(super) unsafe fn walk<B: Tuned>(
+    page: *mut super::Page,
+    arena: &Arena<B>,
+    f: &mut impl FnMut(&BlockInfo),
+) -> usize {
+    let page = page as *mut Page;
+    f(&BlockInfo {
+        ptr: Arena::<B>::page_addr(&(*page).p),
+        size: (*page).real_size,
+        kind: BlockKind::Large,
+        arena: arena as *const Arena<B> as *const u8,
+    });
+    (*page).real_size / B::pagesize()
+}
+
+/// Abort unless `ptr` is the start of the block of a large page.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+#[inline]
+pub(super) unsafe fn check_free<B: Tuned>(page: *mut super::Page, ptr: *mut u8) {
+    if ptr != Arena::<B>::page_addr(page) {
+        check::invalid_free::<B>(ptr, format_args!("not the start of a large block"));
+    }
+}
+
+pub(super) fn good_size<B: Tuned>(layout: Layout) -> usize {
+    layout.size().align_up(B::pagesize())
+}
+
 /// # Safety
 ///
 /// Pointer must be valid.
-pub(super) unsafe fn size(page: *const super::Page, ptr: *mut u8) -> usize {
-    (*(page as *const Page)).real_size - (ptr as usize - page as usize)
+pub(super) unsafe fn size(page: *const super::Page) -> usize {
+    (*(page as *const Page)).real_size
 }
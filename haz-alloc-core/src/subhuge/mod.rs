@@ -1,7 +1,12 @@
+use crate::__internal::UsizeExt;
 use crate::backend::{Mutex, TlsCallback};
-use crate::reserve::{self, ReserveHeader, ReserveType, RESERVE_ALIGN};
-use crate::Backend;
-use crate::__internal::{UsizeExt, SMALL_CLASSES, SMALL_MAX};
+use crate::check;
+use crate::config::{self, Tuned, MAX_ARENAS, MAX_CLASSES, MIN_PAGESIZE};
+use crate::reserve::{self, ReserveHeader, ReserveType};
+use crate::spin::SpinLock;
+use crate::stats::ArenaStats;
+use crate::walk::BlockInfo;
+use crate::{bitset, hardened};
 use core::alloc::Layout;
 use core::cell::{Cell, UnsafeCell};
 use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
@@ -9,25 +14,25 @@ use core::{mem, ptr};
 
 mod large;
 mod small;
+mod tcache;
 
-static ARENAS: [AtomicPtr<()>; 16] = [
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-    AtomicPtr::new(ptr::null_mut()),
-];
+static ARENAS: [AtomicPtr<()>; MAX_ARENAS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_ARENAS];
+
+/// All live arenas, linked through `Arena::all_next` and `Arena::all_prev`.
+///
+/// The lock is never taken while holding the lock of an arena.
+struct Registry {
+    lock: SpinLock,
+    head: UnsafeCell<*mut ()>,
+}
+
+unsafe impl Sync for Registry {}
+
+static REGISTRY: Registry = Registry {
+    lock: SpinLock::new(),
+    head: UnsafeCell::new(ptr::null_mut()),
+};
 
 struct ArenaCell(Cell<*mut ()>);
 
@@ -37,18 +42,18 @@ impl ArenaCell {
     }
 
     #[inline]
-    fn get<B: Backend>(&self) -> *mut Arena<B> {
+    fn get<B: Tuned>(&self) -> *mut Arena<B> {
         self.0.get() as *mut Arena<B>
     }
 
     #[inline]
-    fn set<B: Backend>(&self, ptr: *mut Arena<B>) {
+    fn set<B: Tuned>(&self, ptr: *mut Arena<B>) {
         self.0.set(ptr as *mut ())
     }
 }
 
 #[inline]
-fn tls_arena<B: Backend, T, F>(with: F) -> T
+fn tls_arena<B: Tuned, T, F>(with: F) -> T
 where
     F: FnOnce(&ArenaCell) -> T,
 {
@@ -58,25 +63,21 @@ where
     static CALLBACK: TlsCallback = TlsCallback::new();
 
     #[cold]
-    fn slow<B: Backend>() {
+    fn slow<B: Tuned>() {
         CALLBACK.func.set(Some(|| {
             let arena = TLS_ARENA.get::<B>();
             if !arena.is_null() {
-                for x in ARENAS.iter() {
-                    if x.compare_exchange(
-                        ptr::null_mut(),
-                        arena as *mut (),
-                        Ordering::Release,
-                        Ordering::Relaxed,
-                    )
-                    .is_ok()
-                    {
-                        return;
+                // Blocks freed after this no longer go to the arena.
+                TLS_ARENA.set::<B>(ptr::null_mut());
+                unsafe {
+                    tcache::flush(arena);
+                    (*arena).close_remote();
+                    if B::config().purge_on_exit {
+                        Arena::purge(arena);
+                    } else {
+                        Arena::park(arena);
                     }
                 }
-
-                let guard = unsafe { (*arena).lock.lock() };
-                unsafe { Arena::release(arena, guard) };
             }
         }));
 
@@ -90,24 +91,70 @@ where
     with(&TLS_ARENA)
 }
 
-pub const MAX: usize = 256 * 1024;
+// Head of the remote queue of an arena without owner
+const CLOSED: *mut u8 = ptr::dangling_mut();
 
+/// Metadata of a page of an arena, kept in the metadata array of the arena
+/// instead of the page, which only holds blocks.
+///
+/// The first page of a slab or of a large block has the metadata of the
+/// slab or block, which starts with this.
 #[repr(C)]
-struct Page {
-    r: ReserveHeader,
+pub(super) struct Page {
+    // Small class of the slab starting at the page, -1 for a large block, or
+    // `FREED` once the large block is freed
     class: isize,
+    // Pages back to the first page of the slab or block
+    lead: usize,
 }
 
+// Class of the first page of a freed large block, to catch double frees
+const FREED: isize = -2;
+
+// Size of each entry of the metadata array, fitting the metadata of both
+// slabs and large blocks
+const META_SIZE: usize = mem::size_of::<small::Page>();
+
 #[repr(C)]
-struct Arena<B: Backend> {
-    page: small::Page,
+struct Arena<B: Tuned> {
+    header: ReserveHeader,
 
     rc: UnsafeCell<usize>,
-    vacant: [UnsafeCell<*const small::Page>; SMALL_CLASSES.len()],
+    vacant: [UnsafeCell<*const small::Page>; MAX_CLASSES],
     lock: B::Mutex,
+
+    // Next arena of the same heap
+    next: UnsafeCell<*mut Arena<B>>,
+
+    // Links of the registry
+    all_next: UnsafeCell<*mut Arena<B>>,
+    all_prev: UnsafeCell<*mut Arena<B>>,
+
+    // Live slots of each small class, updated without the lock, and by the
+    // cache of the owner in batches
+    small: [AtomicUsize; MAX_CLASSES],
+    // Live large blocks
+    large: UnsafeCell<usize>,
+
+    // Small blocks freed by other threads, waiting for the owner of the
+    // arena, or `CLOSED` if the arena has no owner
+    remote: AtomicPtr<u8>,
+
+    // State of the generator of page secrets, in hardened mode
+    rng: UnsafeCell<u64>,
 }
 
-impl<B: Backend> Arena<B> {
+impl<B: Tuned> Arena<B> {
+    /// Returns the header of the reservation of the arena.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    unsafe fn header(this: *const Self) -> *const ReserveHeader {
+        ptr::addr_of!((*this).header)
+    }
+
     /// # Safety
     ///
     /// Pointer must be valid.
@@ -116,13 +163,172 @@ impl<B: Backend> Arena<B> {
         if *(*this).rc.get() == 0 {
             drop(guard);
             let this = this as *mut Self;
+            Self::unregister(this);
             ptr::drop_in_place(ptr::addr_of_mut!((*this).lock));
-            reserve::delete::<B>(ptr::addr_of!((*this).page.p.r) as _);
+            reserve::delete::<B>(ptr::addr_of!((*this).header) as _);
+        }
+    }
+
+    /// Put an arena without owner in the pool, or release it if the pool is
+    /// full.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    unsafe fn park(this: *const Self) {
+        for x in ARENAS.iter().take(config::arenas::<B>()) {
+            if x.compare_exchange(
+                ptr::null_mut(),
+                this as *mut (),
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+            {
+                return;
+            }
         }
+
+        let guard = (*this).lock.lock();
+        Arena::release(this, guard);
+    }
+
+    /// Release an arena without owner if it has no block, returning the
+    /// bytes given back, or park it otherwise.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    unsafe fn purge(this: *const Self) -> usize {
+        let guard = (*this).lock.lock();
+        if *(*this).rc.get() == 1 {
+            let bytes = Self::committed_pages(this) * B::pagesize();
+            Arena::release(this, guard);
+            bytes
+        } else {
+            drop(guard);
+            Arena::park(this);
+            0
+        }
+    }
+
+    /// Push a small block freed by another thread to the remote queue,
+    /// returning `false` if the arena has no owner to take it.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid and in a small page of the arena.
+    #[inline]
+    unsafe fn push_remote(&self, ptr: *mut u8) -> bool {
+        let mut head = self.remote.load(Ordering::Relaxed);
+        loop {
+            if head == CLOSED {
+                return false;
+            }
+            *(ptr as *mut *mut u8) = remote_link::<B>(ptr, head);
+            match self
+                .remote
+                .compare_exchange_weak(head, ptr, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(e) => head = e,
+            }
+        }
+    }
+
+    /// Free the blocks of the remote queue.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the owner of the arena.
+    #[inline]
+    unsafe fn drain_remote(&self) {
+        let head = self.remote.load(Ordering::Relaxed);
+        if !head.is_null() && head != CLOSED {
+            free_remote(self, self.remote.swap(ptr::null_mut(), Ordering::Acquire));
+        }
+    }
+
+    /// Let other threads push to the remote queue, once the arena has an
+    /// owner.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the new owner of the arena.
+    #[inline]
+    unsafe fn open_remote(&self) {
+        self.remote.store(ptr::null_mut(), Ordering::Relaxed);
+    }
+
+    /// Stop other threads from pushing to the remote queue, and free the
+    /// blocks already pushed.
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the owner of the arena, before giving it up.
+    unsafe fn close_remote(&self) {
+        free_remote(self, self.remote.swap(CLOSED, Ordering::Acquire));
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid and not registered.
+    unsafe fn register(this: *mut Self) {
+        let _guard = REGISTRY.lock.lock();
+        let head = *REGISTRY.head.get() as *mut Self;
+        *(*this).all_prev.get() = ptr::null_mut();
+        *(*this).all_next.get() = head;
+        if !head.is_null() {
+            *(*head).all_prev.get() = this;
+        }
+        *REGISTRY.head.get() = this as *mut ();
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid and registered.
+    unsafe fn unregister(this: *mut Self) {
+        let _guard = REGISTRY.lock.lock();
+        let prev = *(*this).all_prev.get();
+        let next = *(*this).all_next.get();
+        if !next.is_null() {
+            *(*next).all_prev.get() = prev;
+        }
+        if !prev.is_null() {
+            *(*prev).all_next.get() = next;
+        } else {
+            *REGISTRY.head.get() = next as *mut ();
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid and registered.
+    unsafe fn stats(this: *mut Self) -> ArenaStats {
+        let mut stats = ArenaStats {
+            small: [0; MAX_CLASSES],
+            large: 0,
+            committed_pages: 0,
+            parked: ARENAS
+                .iter()
+                .any(|x| x.load(Ordering::Relaxed) == this as *mut ()),
+        };
+        for (i, size) in B::SMALL_CLASSES.iter().enumerate() {
+            // Blocks freed from other threads may be uncounted before the
+            // cache of the owner counts their allocation.
+            let live = (*this).small[i].load(Ordering::Relaxed) as isize;
+            stats.small[i] = live.max(0) as usize * size;
+        }
+
+        let guard = (*this).lock.lock();
+        stats.large = *(*this).large.get();
+        stats.committed_pages = Self::committed_pages(this);
+        drop(guard);
+        stats
     }
 }
 
-impl<B: Backend> Arena<B> {
+impl<B: Tuned> Arena<B> {
     /// # Safety
     ///
     /// Pointer must be valid.
@@ -130,17 +336,32 @@ impl<B: Backend> Arena<B> {
     /// Lock must be locked.
     #[inline]
     unsafe fn commited(&self) -> *mut [usize] {
-        let (_, offset) = Self::layout();
+        let (_, offset, _) = Self::layout();
         ptr::slice_from_raw_parts_mut(
             (self as *const Self as *const u8).add(offset) as *mut usize,
             Self::commited_len(),
         )
     }
 
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Lock must be locked.
+    #[inline]
+    unsafe fn meta_commited(&self) -> *mut [usize] {
+        let (_, _, offset) = Self::layout();
+        ptr::slice_from_raw_parts_mut(
+            (self as *const Self as *const u8).add(offset) as *mut usize,
+            Self::meta_commited_len(),
+        )
+    }
+
     unsafe fn alloc(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        self.drain_remote();
         let guard = self.lock.lock();
         let rounded_size = layout.size().align_up(layout.align());
-        let x = if rounded_size > SMALL_MAX {
+        let x = if rounded_size > B::SMALL_MAX {
             large::alloc(self, layout)
         } else {
             small::alloc(self, rounded_size, zeroed)
@@ -149,16 +370,175 @@ impl<B: Backend> Arena<B> {
         x
     }
 
+    unsafe fn alloc_batch(&self, class: usize, out: &mut [*mut u8]) -> usize {
+        self.drain_remote();
+        let guard = self.lock.lock();
+        let n = small::alloc_batch(self, class, out);
+        drop(guard);
+        n
+    }
+
+    /// Returns the metadata of the page `index`.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    unsafe fn page(this: *const Self, index: usize) -> *mut Page {
+        (this as *mut u8).add(Self::meta_offset() + index * META_SIZE) as *mut Page
+    }
+
+    /// Returns the index of the page with the metadata `page`.
+    #[inline]
+    fn index_of(page: *const Page) -> usize {
+        ((page as usize) % B::RESERVE_ALIGN - Self::meta_offset()) / META_SIZE
+    }
+
+    /// Returns the address of the page with the metadata `page`.
+    #[inline]
+    fn page_addr(page: *const Page) -> *mut u8 {
+        let arena = (page as usize).align_down(B::RESERVE_ALIGN);
+        (arena + Self::index_of(page) * B::pagesize()) as *mut u8
+    }
+
+    /// Commit the metadata of the `pages` pages from `index`, returning
+    /// `false` if out of memory.
+    ///
+    /// Pages of metadata are commited on first use, and kept until the
+    /// arena is released.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Lock must be locked.
+    unsafe fn commit_meta(this: *const Self, index: usize, pages: usize) -> bool {
+        let pagesize = B::pagesize();
+        let first = index * META_SIZE / pagesize;
+        let last = ((index + pages) * META_SIZE - 1) / pagesize;
+        for i in first..=last {
+            if bitset::get(&*(*this).meta_commited(), i) {
+                continue;
+            }
+            let ptr = (this as *mut u8).add(Self::meta_offset() + i * pagesize);
+            if !reserve::commit::<B>(Self::header(this), ptr, pagesize) {
+                return false;
+            }
+            bitset::set(&mut *(*this).meta_commited(), i);
+        }
+        true
+    }
+
+    /// Returns whether the page `index`, or its metadata if `meta`, is
+    /// commited.
+    ///
+    /// The bitsets are read without the lock, which is enough to check a
+    /// free, as the pages of live blocks stay commited.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    unsafe fn is_commited(this: *const Self, index: usize, meta: bool) -> bool {
+        let bit = |set: *mut [usize], i: usize| {
+            let bits = mem::size_of::<usize>() * 8;
+            let word = &*(set as *const AtomicUsize).add(i / bits);
+            word.load(Ordering::Relaxed) & (1 << (i % bits)) != 0
+        };
+        if !meta {
+            return bit((*this).commited(), index);
+        }
+        let (first, last) = (index * META_SIZE, (index + 1) * META_SIZE - 1);
+        bit((*this).meta_commited(), first / B::pagesize())
+            && bit((*this).meta_commited(), last / B::pagesize())
+    }
+
+    /// Map the pages after `index`, up to `index + pages`, to the slab or
+    /// block starting at `index`.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid, and the metadata of the pages commited.
+    ///
+    /// Lock must be locked.
+    unsafe fn map(this: *const Self, index: usize, pages: usize) {
+        for i in 1..pages {
+            (*Self::page(this, index + i)).lead = i;
+        }
+    }
+
+    /// Unmap the `pages` pages from `index`, as they may start a slab or
+    /// block next.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid, and the metadata of the pages commited.
+    ///
+    /// Lock must be locked.
+    unsafe fn unmap(this: *const Self, index: usize, pages: usize) {
+        for i in index..index + pages {
+            (*Self::page(this, i)).lead = 0;
+        }
+    }
+
+    /// Returns the number of commited pages, metadata included.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Lock must be locked.
+    unsafe fn committed_pages(this: *const Self) -> usize {
+        // Pages of metadata are marked as used up front, but commited on
+        // first use.
+        bitset::count(&*(*this).commited()) - Self::meta_pages()
+            + bitset::count(&*(*this).meta_commited())
+    }
+
+    #[inline]
+    fn pages_len() -> usize {
+        B::RESERVE_ALIGN / B::pagesize()
+    }
+
     #[inline]
     fn commited_len() -> usize {
-        RESERVE_ALIGN / B::pagesize() / mem::size_of::<usize>() / 8
+        Self::pages_len() / mem::size_of::<usize>() / 8
+    }
+
+    #[inline]
+    fn meta_pages() -> usize {
+        (Self::pages_len() * META_SIZE).div_ceil(B::pagesize())
+    }
+
+    #[inline]
+    fn meta_commited_len() -> usize {
+        Self::meta_pages().div_ceil(mem::size_of::<usize>() * 8)
     }
 
+    /// Returns the layout of the arena followed by its bitsets of commited
+    /// pages and of commited pages of metadata, and the offsets of both.
     #[inline]
-    fn layout() -> (Layout, usize) {
-        Layout::new::<Self>()
+    fn layout() -> (Layout, usize, usize) {
+        let (layout, commited) = Layout::new::<Self>()
             .extend(Layout::array::<usize>(Self::commited_len()).unwrap())
-            .unwrap()
+            .unwrap();
+        let (layout, meta_commited) = layout
+            .extend(Layout::array::<usize>(Self::meta_commited_len()).unwrap())
+            .unwrap();
+        (layout, commited, meta_commited)
+    }
+
+    /// Returns the offset of the metadata array, from the first page after
+    /// the arena.
+    #[inline]
+    fn meta_offset() -> usize {
+        Self::layout().0.size().align_up(B::pagesize())
+    }
+
+    /// Returns the number of pages holding the arena and its metadata,
+    /// before the first page of blocks.
+    #[inline]
+    fn header_pages() -> usize {
+        Self::meta_offset() / B::pagesize() + Self::meta_pages()
     }
 
     fn new() -> *mut Self {
@@ -169,34 +549,421 @@ impl<B: Backend> Arena<B> {
             }
         }
 
-        let (_, ptr) = reserve::new::<B>(RESERVE_ALIGN, ReserveType::SubHuge);
+        Self::create()
+    }
+
+    /// Reserve a new arena, without looking at the pool.
+    fn create() -> *mut Self {
+        let (_, ptr) = reserve::new::<B>(B::RESERVE_ALIGN, B::RESERVE_ALIGN, ReserveType::SubHuge);
         let ptr = ptr as *mut Self;
+        if ptr.is_null() {
+            return ptr;
+        }
         unsafe {
-            (*ptr).page.rc = AtomicUsize::new(1);
-            let mut start = (ptr as *mut u8).add(Self::layout().0.size());
-            start = start.add(start.align_offset(SMALL_CLASSES[0]));
-            (*ptr).page.vacancy = AtomicUsize::new(
-                (B::pagesize() - (start as usize - ptr as usize)) / SMALL_CLASSES[0],
-            );
-            (*ptr).page.zeroed = UnsafeCell::new(start);
+            // The first page is commited with the reservation, and the
+            // metadata as pages get used.
+            let pagesize = B::pagesize();
+            let pages = Self::meta_offset() / pagesize;
+            if pages > 1
+                && !reserve::commit::<B>(
+                    Self::header(ptr),
+                    (ptr as *mut u8).add(pagesize),
+                    (pages - 1) * pagesize,
+                )
+            {
+                reserve::delete::<B>(ptr::addr_of_mut!((*ptr).header));
+                return ptr::null_mut();
+            }
 
             (*ptr).rc = UnsafeCell::new(1);
+            (*ptr).remote = AtomicPtr::new(CLOSED);
+            if hardened::ENABLED {
+                (*ptr).rng = UnsafeCell::new(hardened::seed::<B>(ptr as usize));
+            }
             B::Mutex::new(ptr::addr_of_mut!((*ptr).lock));
-            (*ptr).vacant[0] = UnsafeCell::new(&(*ptr).page);
-            (*(*ptr).commited())[0] = 1;
+            bitset::set_range(&mut *(*ptr).commited(), 0, Self::header_pages());
+
+            Self::register(ptr);
         }
 
         ptr
     }
 }
 
+/// Arenas owned by a heap instead of a thread.
+///
+/// The heap holds a reference to each of its arenas, so they live until
+/// they are all deleted at once.
+pub(super) struct HeapArenas<B: Tuned> {
+    head: *mut Arena<B>,
+}
+
+impl<B: Tuned> HeapArenas<B> {
+    pub(super) const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// Layout must be valid for a subhuge block.
+    pub(super) unsafe fn alloc(&mut self, layout: Layout, zeroed: bool) -> *mut u8 {
+        let mut arena = self.head;
+        while !arena.is_null() {
+            let ptr = (*arena).alloc(layout, zeroed);
+            if !ptr.is_null() {
+                return ptr;
+            }
+            arena = *(*arena).next.get();
+        }
+
+        let arena = Arena::<B>::create();
+        if arena.is_null() {
+            return ptr::null_mut();
+        }
+        *(*arena).next.get() = self.head;
+        self.head = arena;
+        (*arena).alloc(layout, zeroed)
+    }
+
+    /// Unreserve all arenas, regardless of the blocks still allocated in
+    /// them.
+    ///
+    /// # Safety
+    ///
+    /// No block of these arenas may be used afterwards.
+    pub(super) unsafe fn delete(&mut self) {
+        while !self.head.is_null() {
+            let arena = self.head;
+            self.head = *(*arena).next.get();
+            Arena::unregister(arena);
+            ptr::drop_in_place(ptr::addr_of_mut!((*arena).lock));
+            reserve::delete::<B>(ptr::addr_of_mut!((*arena).header));
+        }
+    }
+}
+
+/// Release the arenas of the pool that have no block, returning the bytes
+/// given back.
+///
+/// Empty pages are decommited as soon as their last block is freed, so
+/// only whole arenas are left to release.
+pub(super) fn purge<B: Tuned>() -> usize {
+    let mut bytes = 0;
+    for x in ARENAS.iter() {
+        let arena = x.swap(ptr::null_mut(), Ordering::Acquire) as *const Arena<B>;
+        if !arena.is_null() {
+            bytes += unsafe { Arena::purge(arena) };
+        }
+    }
+    bytes
+}
+
+/// Give up the arena of the current thread, releasing it if it has no
+/// block, and returning the bytes given back.
+pub(super) fn flush<B: Tuned>() -> usize {
+    tls_arena::<B, _, _>(|tls_arena| {
+        let arena = tls_arena.get::<B>();
+        if arena.is_null() {
+            return 0;
+        }
+        tls_arena.set::<B>(ptr::null_mut());
+        unsafe {
+            tcache::flush(arena);
+            (*arena).close_remote();
+            Arena::purge(arena)
+        }
+    })
+}
+
+/// Call `f` with the statistics of each live arena.
+///
+/// The cache of the current thread is counted first, but blocks allocated
+/// from or freed to the cache of other threads may not be counted yet.
+pub(super) fn arena_stats<B: Tuned>(mut f: impl FnMut(&ArenaStats)) {
+    tls_arena::<B, _, _>(|tls_arena| {
+        let arena = tls_arena.get::<B>();
+        if !arena.is_null() {
+            unsafe { tcache::fold(arena) };
+        }
+    });
+
+    let _guard = REGISTRY.lock.lock();
+    let mut arena = unsafe { *REGISTRY.head.get() } as *mut Arena<B>;
+    while !arena.is_null() {
+        unsafe {
+            f(&Arena::stats(arena));
+            arena = *(*arena).all_next.get();
+        }
+    }
+}
+
+/// Call `f` with each live block of each arena.
+///
+/// The cache of the current thread is flushed first, but blocks in the
+/// cache of other threads, or waiting in a remote queue, are reported as
+/// live.
+pub(super) fn walk<B: Tuned>(f: &mut impl FnMut(&BlockInfo)) {
+    tls_arena::<B, _, _>(|tls_arena| {
+        let arena = tls_arena.get::<B>();
+        if !arena.is_null() {
+            unsafe { tcache::flush(arena) };
+        }
+    });
+
+    let _guard = REGISTRY.lock.lock();
+    let pagesize = B::pagesize();
+    let mut arena = unsafe { *REGISTRY.head.get() } as *mut Arena<B>;
+    while !arena.is_null() {
+        unsafe {
+            let guard = (*arena).lock.lock();
+            let commited = &*(*arena).commited();
+            let mut index = Arena::<B>::header_pages();
+            while index < B::RESERVE_ALIGN / pagesize {
+                if !bitset::get(commited, index) {
+                    index += 1;
+                    continue;
+                }
+
+                let page = Arena::page(arena, index);
+                if (*page).class == -1 {
+                    index += large::walk(page, &*arena, f);
+                } else {
+                    index += small::walk(page as *const small::Page, &*arena, f);
+                }
+            }
+            drop(guard);
+            arena = *(*arena).all_next.get();
+        }
+    }
+}
+
+/// Fails the build unless an arena has room for its metadata and 64 pages of
+/// blocks, with pages of `MIN_PAGESIZE`.
+pub(crate) const fn validate<B: Tuned>() {
+    let pages = B::RESERVE_ALIGN / MIN_PAGESIZE;
+    let bits = mem::size_of::<usize>() * 8;
+    let meta_pages = (pages * META_SIZE).div_ceil(MIN_PAGESIZE);
+    let bitsets = (pages / bits + meta_pages.div_ceil(bits)) * mem::size_of::<usize>();
+    let header_pages = (mem::size_of::<Arena<B>>() + bitsets).div_ceil(MIN_PAGESIZE) + meta_pages;
+    assert!(
+        pages >= 64 + header_pages,
+        "haz-alloc: the reservation alignment leaves no room for 64 pages of blocks in an arena"
+    );
+}
+
+/// Returns whether `header` is the header of a live arena.
+pub(super) fn is_arena<B: Tuned>(header: *mut ReserveHeader) -> bool {
+    let _guard = REGISTRY.lock.lock();
+    let mut arena = unsafe { *REGISTRY.head.get() } as *mut Arena<B>;
+    while !arena.is_null() {
+        if arena as *mut ReserveHeader == header {
+            return true;
+        }
+        arena = unsafe { *(*arena).all_next.get() };
+    }
+    false
+}
+
+/// Abort unless `ptr` is the start of a live block of the arena.
+///
+/// # Safety
+///
+/// Arena must be valid.
+#[inline]
+pub(super) unsafe fn check_free<B: Tuned>(arena: *const ReserveHeader, ptr: *mut u8) {
+    let arena = arena as *const Arena<B>;
+    check_block(arena, page_to_free(arena, ptr), ptr)
+}
+
+/// Like `check_free`, for a block of `class`, or a large block if `-1`,
+/// returning the metadata of its page.
+///
+/// Only the checks of `class` are done if the page has that class.
+///
+/// # Safety
+///
+/// Arena must be valid.
+#[inline]
+pub(super) unsafe fn check_free_sized<B: Tuned>(
+    arena: *const ReserveHeader,
+    ptr: *mut u8,
+    class: isize,
+) -> *mut Page {
+    let arena = arena as *const Arena<B>;
+    let page = page_to_free(arena, ptr);
+    if (*page).class != class {
+        check_block(arena, page, ptr);
+        // In debug mode, the size of the layout is reported by
+        // `debug::on_free_sized` instead.
+        if !B::DEBUG {
+            check::invalid_free::<B>(ptr, format_args!("layout does not match its page"));
+        }
+    } else if class == -1 {
+        large::check_free::<B>(page, ptr)
+    } else {
+        small::check_free(page as _, &*arena, ptr, class as usize)
+    }
+    page
+}
+
+/// Returns the metadata of the page of `ptr`, aborting unless it is in the
+/// commited pages of blocks of `arena`.
+///
+/// # Safety
+///
+/// Arena must be valid.
+#[inline]
+unsafe fn page_to_free<B: Tuned>(arena: *const Arena<B>, ptr: *mut u8) -> *mut Page {
+    let index = (ptr as usize - arena as usize) / B::pagesize();
+    if index < Arena::<B>::header_pages() {
+        check::invalid_free::<B>(ptr, format_args!("in the header of an arena"));
+    }
+    if !Arena::is_commited(arena, index, true) {
+        check::invalid_free::<B>(ptr, format_args!("not in a page of blocks"));
+    }
+    let page = page_of::<B>(ptr);
+    if !Arena::is_commited(arena, index, false) {
+        // Freed blocks leave their metadata behind.
+        let class = (*page).class;
+        if class == FREED || (class >= 0 && (class as usize) < B::SMALL_CLASSES.len()) {
+            check::double_free::<B>(ptr, format_args!("no live block in its page"));
+        }
+        check::invalid_free::<B>(ptr, format_args!("not in a page of blocks"));
+    }
+    page
+}
+
+/// Abort unless `ptr` is the start of a live block of `page`.
+///
+/// # Safety
+///
+/// Arena and page must be valid.
+#[inline]
+unsafe fn check_block<B: Tuned>(arena: *const Arena<B>, page: *mut Page, ptr: *mut u8) {
+    let class = (*page).class;
+    if class == -1 {
+        large::check_free::<B>(page, ptr)
+    } else if class >= 0 && (class as usize) < B::SMALL_CLASSES.len() {
+        small::check_free(page as _, &*arena, ptr, class as usize)
+    } else if class == FREED {
+        check::double_free::<B>(ptr, format_args!("no live block in its page"));
+    } else {
+        check::invalid_free::<B>(ptr, format_args!("not in a page of blocks"));
+    }
+}
+
+/// Returns the link to `next` held by the slot `slot` of a remote queue,
+/// or the other way around.
+///
+/// In hardened mode, links are encoded like those of free lists, with the
+/// secret of the page of the slot.
+///
+/// # Safety
+///
+/// Pointer must be valid and in a small page.
+#[inline]
+unsafe fn remote_link<B: Tuned>(slot: *mut u8, next: *mut u8) -> *mut u8 {
+    if !hardened::ENABLED {
+        return next;
+    }
+    let page = page_of::<B>(slot) as *const small::Page;
+    (next as usize ^ slot as usize ^ (*page).secret) as *mut u8
+}
+
+/// Abort unless `next`, linked from the slot `slot` of the remote queue of
+/// `arena`, is null or a slot of the arena.
+///
 /// # Safety
 ///
 /// Pointer must be valid.
-pub(super) unsafe fn alloc<B: Backend>(layout: Layout, zeroed: bool) -> *mut u8 {
+///
+/// Lock must be locked.
+unsafe fn check_remote<B: Tuned>(arena: *const Arena<B>, slot: *mut u8, next: *mut u8) {
+    if next.is_null() {
+        return;
+    }
+    // The page is looked up only once known to be in use.
+    let valid = (next as usize - 1).align_down(B::RESERVE_ALIGN) == arena as usize && {
+        let index = (next as usize - arena as usize) / B::pagesize();
+        index >= Arena::<B>::header_pages()
+            && bitset::get(&*(*arena).commited(), index)
+            && small::is_slot::<B>(page_of::<B>(next) as _, next)
+    };
+    if !valid {
+        B::abort(format_args!(
+            "haz-alloc: corrupted remote queue of arena {:p}, at {:p}",
+            arena, slot
+        ));
+    }
+}
+
+/// Free a chain of small blocks of `arena` linked through their first word.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+unsafe fn free_remote<B: Tuned>(arena: *const Arena<B>, mut head: *mut u8) {
+    let mut buf = [ptr::null_mut(); 64];
+    while !head.is_null() && head != CLOSED {
+        // Links are checked with the lock held, so that pages stay in use.
+        let guard = hardened::ENABLED.then(|| (*arena).lock.lock());
+        let mut len = 0;
+        while len < buf.len() && !head.is_null() {
+            buf[len] = head;
+            let next = remote_link::<B>(head, *(head as *mut *mut u8));
+            if hardened::ENABLED {
+                check_remote(arena, head, next);
+            }
+            head = next;
+            len += 1;
+        }
+        drop(guard);
+
+        // Sort so that blocks of the same page are freed together.
+        let slots = &mut buf[..len];
+        slots.sort_unstable();
+        let mut rest = &slots[..];
+        while !rest.is_empty() {
+            let arena = (rest[0] as usize - 1).align_down(B::RESERVE_ALIGN) as *const ReserveHeader;
+            rest = &rest[dealloc_batch::<B>(arena, rest)..];
+        }
+    }
+}
+
+/// Make `arena` the arena of the current thread, giving up the previous one.
+///
+/// # Safety
+///
+/// Arena must be valid and without owner.
+unsafe fn adopt<B: Tuned>(tls_arena: &ArenaCell, arena: *mut Arena<B>) {
+    let old = tls_arena.get::<B>();
+    if !old.is_null() {
+        (*old).close_remote();
+        let guard = (*old).lock.lock();
+        Arena::release(old, guard);
+    }
+
+    (*arena).open_remote();
+    tls_arena.set(arena);
+}
+
+/// # Safety
+///
+/// Pointer must be valid.
+pub(super) unsafe fn alloc<B: Tuned>(layout: Layout, zeroed: bool) -> *mut u8 {
     tls_arena::<B, _, _>(|tls_arena| {
         let x = tls_arena.get::<B>();
         if !x.is_null() {
+            let rounded_size = layout.size().align_up(layout.align());
+            if !zeroed && rounded_size <= B::SMALL_MAX {
+                let ptr = tcache::alloc(x, B::class_of(rounded_size));
+                if !ptr.is_null() {
+                    return ptr;
+                }
+            }
+
             let ptr = (*x).alloc(layout, zeroed);
             if !ptr.is_null() {
                 return ptr;
@@ -205,7 +972,7 @@ pub(super) unsafe fn alloc<B: Backend>(layout: Layout, zeroed: bool) -> *mut u8
 
         let arena = Arena::<B>::new();
         if !arena.is_null() {
-            tls_arena.set(arena);
+            adopt(tls_arena, arena);
             let ptr = (*arena).alloc(layout, zeroed);
             if !ptr.is_null() {
                 return ptr;
@@ -215,25 +982,50 @@ pub(super) unsafe fn alloc<B: Backend>(layout: Layout, zeroed: bool) -> *mut u8
     })
 }
 
+/// Fill `out` with blocks of `class`, taking the arena lock once per batch.
+pub(super) unsafe fn alloc_batch<B: Tuned>(class: usize, out: &mut [*mut u8]) -> usize {
+    tls_arena::<B, _, _>(|tls_arena| {
+        let mut n = 0;
+        let x = tls_arena.get::<B>();
+        if !x.is_null() {
+            n = (*x).alloc_batch(class, out);
+        }
+
+        while n < out.len() {
+            let arena = Arena::<B>::new();
+            if arena.is_null() {
+                break;
+            }
+            adopt(tls_arena, arena);
+            let m = (*arena).alloc_batch(class, &mut out[n..]);
+            if m == 0 {
+                break;
+            }
+            n += m;
+        }
+        n
+    })
+}
+
 /// # Safety
 ///
 /// Pointer must be valid.
-pub(super) unsafe fn realloc_in_place<B: Backend>(
+pub(super) unsafe fn realloc_in_place<B: Tuned>(
     arena: *const ReserveHeader,
     ptr: *mut u8,
     layout: Layout,
 ) -> bool {
     let arena = arena as *const Arena<B>;
-    let page = (ptr as usize - 1).align_down(B::pagesize()) as *mut Page;
+    let page = page_of::<B>(ptr);
     let class = (*page).class;
     let rounded_size = layout.size().align_up(layout.align());
 
     if class == -1 {
-        if rounded_size > SMALL_MAX {
+        if rounded_size > B::SMALL_MAX {
             return large::realloc_in_place(page, &*arena, layout);
         }
-    } else if rounded_size <= SMALL_MAX {
-        return small::realloc_in_place(class, rounded_size);
+    } else if rounded_size <= B::SMALL_MAX {
+        return small::realloc_in_place::<B>(class, rounded_size);
     }
 
     false
@@ -242,28 +1034,147 @@ pub(super) unsafe fn realloc_in_place<B: Backend>(
 /// # Safety
 ///
 /// Pointer must be valid.
-pub(super) unsafe fn dealloc<B: Backend>(arena: *const ReserveHeader, ptr: *mut u8) {
+pub(super) unsafe fn dealloc<B: Tuned>(arena: *const ReserveHeader, ptr: *mut u8) {
     let arena = arena as *const Arena<B>;
-    let page = (ptr as usize - 1).align_down(B::pagesize()) as *mut Page;
+    let page = page_of::<B>(ptr);
     let class = (*page).class;
 
     if class == -1 {
         large::dealloc(page, arena)
     } else {
-        small::dealloc(page as _, arena, ptr, class)
+        dealloc_small_slot(page as _, arena, ptr, class as usize)
+    }
+}
+
+/// Deallocate a small slot, through the cache of the thread if the slot is
+/// in its arena.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+#[inline]
+unsafe fn dealloc_small_slot<B: Tuned>(
+    page: *const small::Page,
+    arena: *const Arena<B>,
+    ptr: *mut u8,
+    class: usize,
+) {
+    let tls = tls_arena::<B, _, _>(|tls_arena| tls_arena.get::<B>());
+    if ptr::eq(tls, arena) {
+        tcache::dealloc(arena, ptr, class)
+    } else if !(*arena).push_remote(ptr) {
+        small::dealloc(page, arena, ptr, class as isize)
+    }
+}
+
+/// Deallocate the leading blocks of `ptrs` that share a small page with the
+/// first one at once, returning how many were deallocated, or 0 if the
+/// first block is not small.
+///
+/// # Safety
+///
+/// Pointers must be valid, and `ptrs` must not be empty.
+pub(super) unsafe fn dealloc_batch<B: Tuned>(
+    arena: *const ReserveHeader,
+    ptrs: &[*mut u8],
+) -> usize {
+    let arena = arena as *const Arena<B>;
+    let page = page_of::<B>(ptrs[0]);
+    let class = (*page).class;
+    if class == -1 {
+        return 0;
+    }
+
+    // Blocks of other reservations have no page map to look up.
+    let len = ptrs
+        .iter()
+        .position(|x| {
+            (*x as usize - 1).align_down(B::RESERVE_ALIGN) != arena as usize
+                || page_of::<B>(*x) != page
+        })
+        .unwrap_or(ptrs.len());
+    small::dealloc_many(page as _, arena, &ptrs[..len], class);
+    len
+}
+
+/// Returns the metadata of the first page of the slab or block of `ptr`.
+///
+/// # Safety
+///
+/// Pointer must be in a page of blocks of an arena.
+#[inline]
+unsafe fn page_of<B: Tuned>(ptr: *mut u8) -> *mut Page {
+    let arena = (ptr as usize - 1).align_down(B::RESERVE_ALIGN) as *const Arena<B>;
+    let index = (ptr as usize - arena as usize) / B::pagesize();
+    Arena::page(arena, index - (*Arena::page(arena, index)).lead)
+}
+
+/// Returns the small class of blocks for `layout`, if they are always
+/// allocated in a small page.
+#[inline]
+pub(super) fn small_class<B: Tuned>(layout: Layout) -> Option<usize> {
+    let rounded_size = layout.size().align_up(layout.align());
+    if rounded_size <= B::SMALL_MAX && layout.align() <= B::pagesize() {
+        Some(B::class_of(rounded_size))
+    } else {
+        None
     }
 }
 
+/// Deallocate a small slot of `class`, with the metadata `page` returned by
+/// `check_free_sized`.
+///
+/// # Safety
+///
+/// Pointers must be valid and in a small page of `class`.
+#[inline]
+pub(super) unsafe fn dealloc_small<B: Tuned>(
+    arena: *const ReserveHeader,
+    page: *mut Page,
+    ptr: *mut u8,
+    class: usize,
+) {
+    dealloc_small_slot(page as _, arena as *const Arena<B>, ptr, class)
+}
+
+/// Deallocate a large block, with the metadata `page` returned by
+/// `check_free_sized`.
+///
+/// # Safety
+///
+/// Pointers must be valid and in a large page.
+#[inline]
+pub(super) unsafe fn dealloc_large<B: Tuned>(arena: *const ReserveHeader, page: *mut Page) {
+    large::dealloc(page, arena as *const Arena<B>)
+}
+
+pub(super) fn good_size<B: Tuned>(layout: Layout) -> usize {
+    let rounded_size = layout.size().align_up(layout.align());
+    if rounded_size > B::SMALL_MAX {
+        large::good_size::<B>(layout)
+    } else {
+        B::SMALL_CLASSES[B::class_of(rounded_size)]
+    }
+}
+
+/// # Safety
+///
+/// Pointer must be valid.
+pub(super) unsafe fn is_small<B: Tuned>(ptr: *mut u8) -> bool {
+    let page = page_of::<B>(ptr);
+    (*page).class != -1
+}
+
 /// # Safety
 ///
 /// Pointer must be valid.
-pub(super) unsafe fn size<B: Backend>(ptr: *mut u8) -> usize {
-    let page = (ptr as usize - 1).align_down(B::pagesize()) as *mut Page;
+pub(super) unsafe fn size<B: Tuned>(ptr: *mut u8) -> usize {
+    let page = page_of::<B>(ptr);
     let class = (*page).class;
 
     if class == -1 {
-        large::size(page, ptr)
+        large::size(page)
     } else {
-        SMALL_CLASSES[class as usize]
+        B::SMALL_CLASSES[class as usize]
     }
 }
@@ -1,11 +1,11 @@
-use super::Arena;
-use crate::__internal::{small_class_of, SMALL_CLASSES};
+use super::{tcache, Arena};
 use crate::backend::Mutex;
-use crate::bitset;
-use crate::Backend;
+use crate::config::Tuned;
+use crate::walk::{BlockInfo, BlockKind};
+use crate::{bitset, check, hardened, reserve};
 use core::cell::UnsafeCell;
-use core::{ptr, mem};
 use core::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
+use core::{cmp, ptr};
 
 #[repr(C)]
 pub(super) struct Page {
@@ -18,24 +18,68 @@ pub(super) struct Page {
     pub rc: AtomicUsize,
     pub free: AtomicPtr<u8>,
     pub zeroed: UnsafeCell<*mut u8>,
+
+    // Key of the links of the free list, in hardened mode
+    pub secret: usize,
 }
 
 impl Page {
+    /// Returns the slot after `slot` in the free list.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be a free slot of the page.
+    #[inline]
+    unsafe fn next<B: Tuned>(&self, slot: *mut u8) -> *mut u8 {
+        let next = *(slot as *mut *mut u8);
+        if !hardened::ENABLED {
+            return next;
+        }
+
+        let next = (next as usize ^ slot as usize ^ self.secret) as *mut u8;
+        let slab = Arena::<B>::page_addr(&self.p);
+        let end = slab as usize + B::slab_size(self.p.class as usize);
+        if !next.is_null() && (next < slab || next as usize >= end) {
+            B::abort(format_args!(
+                "haz-alloc: corrupted free list in page {:p} of small class {}, at {:p}",
+                slab,
+                B::SMALL_CLASSES[self.p.class as usize],
+                slot
+            ));
+        }
+        next
+    }
+
+    /// Link the free slot `slot` to `next`.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be a free slot of the page.
+    #[inline]
+    unsafe fn set_next(&self, slot: *mut u8, next: *mut u8) {
+        let next = if hardened::ENABLED {
+            (next as usize ^ slot as usize ^ self.secret) as *mut u8
+        } else {
+            next
+        };
+        *(slot as *mut *mut u8) = next;
+    }
+
     /// # Safety
     ///
     /// Pointer must be valid.
     ///
     /// Lock must be locked.
-    unsafe fn alloc_from_free<B: Backend>(&self, arena: &Arena<B>, class: usize) -> *mut u8 {
+    unsafe fn alloc_from_free<B: Tuned>(&self, arena: &Arena<B>, class: usize) -> *mut u8 {
         let mut free = self.free.load(Ordering::Acquire);
         while !free.is_null() {
-            let next = *(free as *mut *mut u8);
+            let next = self.next::<B>(free);
             match self
                 .free
                 .compare_exchange_weak(free, next, Ordering::Acquire, Ordering::Acquire)
             {
                 Ok(_) => {
-                    self.increase_rc(arena, class);
+                    self.increase_rc(arena, class, 1);
                     return free;
                 }
                 Err(a) => free = a,
@@ -51,32 +95,110 @@ impl Page {
     ///
     /// Lock must be locked.
     #[inline]
-    unsafe fn alloc_from_zeroed<B: Backend>(&self, arena: &Arena<B>, class: usize) -> *mut u8 {
-        let next_page = (self as *const Self as *mut u8).add(B::pagesize());
+    unsafe fn alloc_from_zeroed<B: Tuned>(&self, arena: &Arena<B>, class: usize) -> *mut u8 {
+        let end = Arena::<B>::page_addr(&self.p).add(B::slab_size(class));
         let ptr = *self.zeroed.get();
-        if ptr < next_page {
-            *self.zeroed.get() = ptr.add(SMALL_CLASSES[self.p.class as usize]);
-            self.increase_rc(arena, class);
+        if ptr.add(B::SMALL_CLASSES[class]) <= end {
+            *self.zeroed.get() = ptr.add(B::SMALL_CLASSES[class]);
+            self.increase_rc(arena, class, 1);
             return ptr;
         }
 
         ptr::null_mut()
     }
 
+    /// Fill `out` with as many slots as available, taking the whole free list
+    /// at once before bumping the zeroed pointer.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Lock must be locked.
+    unsafe fn alloc_many<B: Tuned>(
+        &self,
+        arena: &Arena<B>,
+        class: usize,
+        out: &mut [*mut u8],
+    ) -> usize {
+        // Deallocations only ever increase the vacancy, after pushing to the
+        // free list, so there are at least this many slots available.
+        let len = cmp::min(out.len(), self.vacancy.load(Ordering::Acquire));
+        let mut n = 0;
+
+        let mut free = self.free.swap(ptr::null_mut(), Ordering::Acquire);
+        while n < len && !free.is_null() {
+            out[n] = free;
+            n += 1;
+            free = self.next::<B>(free);
+        }
+        if !free.is_null() {
+            let mut tail = free;
+            loop {
+                let next = self.next::<B>(tail);
+                if next.is_null() {
+                    break;
+                }
+                tail = next;
+            }
+            self.push_free(free, tail);
+        }
+
+        let size = B::SMALL_CLASSES[class];
+        let mut zeroed = *self.zeroed.get();
+        while n < len {
+            out[n] = zeroed;
+            zeroed = zeroed.add(size);
+            n += 1;
+        }
+        *self.zeroed.get() = zeroed;
+
+        if n > 0 {
+            self.increase_rc(arena, class, n);
+        }
+        n
+    }
+
+    /// Push the chain of slots from `head` to `tail` to the free list.
+    ///
+    /// # Safety
+    ///
+    /// Pointers must be valid.
+    #[inline]
+    unsafe fn push_free(&self, head: *mut u8, tail: *mut u8) {
+        let mut next = self.free.load(Ordering::Relaxed);
+        loop {
+            self.set_next(tail, next);
+            match self
+                .free
+                .compare_exchange_weak(next, head, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(e) => next = e,
+            }
+        }
+    }
+
     /// # Safety
     ///
     /// Pointer must be valid.
     ///
     /// Lock must be unlocked.
     #[inline]
-    unsafe fn reduce_rc<B: Backend>(this: *const Page, arena: *const Arena<B>, class: usize) {
-        let add_to_vacant = (*this).vacancy.fetch_add(1, Ordering::Release) == 0;
+    unsafe fn reduce_rc<B: Tuned>(
+        this: *const Page,
+        arena: *const Arena<B>,
+        class: usize,
+        n: usize,
+    ) {
+        (*arena).small[class].fetch_sub(n, Ordering::Relaxed);
+        let add_to_vacant = (*this).vacancy.fetch_add(n, Ordering::Release) == 0;
         if !add_to_vacant {
             let mut rc = (*this).rc.load(Ordering::Relaxed);
-            while rc != 1 {
+            while rc > n {
                 match (*this).rc.compare_exchange_weak(
                     rc,
-                    rc - 1,
+                    rc - n,
                     Ordering::Release,
                     Ordering::Relaxed,
                 ) {
@@ -88,15 +210,23 @@ impl Page {
 
         let guard = (*arena).lock.lock();
 
-        if (*this).rc.fetch_sub(1, Ordering::Relaxed) == 1 {
+        if (*this).rc.fetch_sub(n, Ordering::Relaxed) == n {
             atomic::fence(Ordering::Acquire);
 
             if !add_to_vacant {
                 (*this).remove_from_vacant(&*arena, class);
             }
-            let index = ((this as usize) - (arena as usize)) / B::pagesize();
-            bitset::clear(&mut *(*arena).commited(), index);
-            B::mdecommit(this as _, B::pagesize());
+            let slab = B::slab_size(class);
+            let index = Arena::<B>::index_of(&(*this).p);
+            let pages = slab / B::pagesize();
+            if pages == 1 {
+                bitset::clear(&mut *(*arena).commited(), index);
+            } else {
+                Arena::unmap(arena, index + 1, pages - 1);
+                bitset::clear_range(&mut *(*arena).commited(), index, pages);
+            }
+            let addr = Arena::<B>::page_addr(&(*this).p);
+            reserve::decommit::<B>(Arena::header(arena), addr, slab);
 
             Arena::release(arena, guard);
         } else if add_to_vacant {
@@ -109,9 +239,10 @@ impl Page {
     /// Pointer must be valid.
     ///
     /// Lock must be locked.
-    unsafe fn increase_rc<B: Backend>(&self, arena: &Arena<B>, class: usize) {
-        self.rc.fetch_add(1, Ordering::Relaxed);
-        if self.vacancy.fetch_sub(1, Ordering::Release) == 1 {
+    unsafe fn increase_rc<B: Tuned>(&self, arena: &Arena<B>, class: usize, n: usize) {
+        arena.small[class].fetch_add(n, Ordering::Relaxed);
+        self.rc.fetch_add(n, Ordering::Relaxed);
+        if self.vacancy.fetch_sub(n, Ordering::Release) == n {
             atomic::fence(Ordering::Acquire);
             self.remove_from_vacant(arena, class);
         }
@@ -122,7 +253,7 @@ impl Page {
     /// Pointer must be valid.
     ///
     /// Lock must be locked.
-    unsafe fn add_to_vacant<B: Backend>(&self, arena: &Arena<B>, class: usize) {
+    unsafe fn add_to_vacant<B: Tuned>(&self, arena: &Arena<B>, class: usize) {
         let next = *arena.vacant[class].get();
         *self.next.get() = next;
         if !next.is_null() {
@@ -136,7 +267,7 @@ impl Page {
     /// Pointer must be valid.
     ///
     /// Lock must be locked.
-    unsafe fn remove_from_vacant<B: Backend>(&self, arena: &Arena<B>, class: usize) {
+    unsafe fn remove_from_vacant<B: Tuned>(&self, arena: &Arena<B>, class: usize) {
         let prev = *self.prev.get();
         let next = *self.next.get();
         if !next.is_null() {
@@ -150,79 +281,299 @@ impl Page {
         *self.prev.get() = ptr::null_mut();
         *self.next.get() = ptr::null_mut();
     }
+
+    /// Put all `n` slots of `size` of a new page in the free list, in a
+    /// random order.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Lock must be locked.
+    unsafe fn shuffle<B: Tuned>(&self, arena: &Arena<B>, n: usize, size: usize) {
+        let rng = &mut *arena.rng.get();
+        let first = *self.zeroed.get();
+        let word = |i: usize| first.add(i * size) as *mut usize;
+
+        // Link the slots in a random cycle with Sattolo's shuffle, keeping
+        // the index of the next slot in the first word of each slot.
+        for i in 0..n {
+            *word(i) = i;
+        }
+        for i in (1..n).rev() {
+            ptr::swap(word(i), word(hardened::below(rng, i)));
+        }
+
+        // Cut the cycle before a random slot, which heads the list.
+        let head = hardened::below(rng, n);
+        for i in 0..n {
+            let next = *word(i);
+            let next = if next == head {
+                ptr::null_mut()
+            } else {
+                first.add(next * size)
+            };
+            self.set_next(first.add(i * size), next);
+        }
+        self.free.store(first.add(head * size), Ordering::Relaxed);
+        *self.zeroed.get() = first.add(n * size);
+    }
+}
+
+/// Returns the first slot of a page, at the start of its slab.
+///
+/// Slots are laid out with the class size from the start of the slab,
+/// which is aligned to a page, so every slot keeps the alignment of any
+/// layout rounding up to the class.
+#[inline]
+fn first_slot<B: Tuned>(page: *const Page) -> *mut u8 {
+    Arena::<B>::page_addr(page as *const super::Page)
 }
 
+/// Commit a new page for `class` and add it to the vacant list.
+///
+/// The page spans the whole slab of the class, which may be several pages
+/// of the system, and has its metadata in the first entry of the slab in
+/// the metadata array.
+///
 /// # Safety
 ///
 /// Pointer must be valid.
 ///
 /// Lock must be locked.
-pub(super) unsafe fn alloc<B: Backend>(arena: &Arena<B>, size: usize, zeroed: bool) -> *mut u8 {
-    let class = small_class_of(size);
-
-    let page = *arena.vacant[class].get();
-    if !page.is_null() {
-        if !zeroed {
-            let ptr = (*page).alloc_from_free(arena, class);
-            if !ptr.is_null() {
-                return ptr;
-            }
-            return (*page).alloc_from_zeroed(arena, class);
-        } else {
-            let ptr = (*page).alloc_from_zeroed(arena, class);
-            if !ptr.is_null() {
-                return ptr;
-            }
-            let ptr = (*page).alloc_from_free(arena, class);
-            if !ptr.is_null() {
-                ptr.write_bytes(0, size);
-            }
-            return ptr;
-        }
-    }
-
-    let index = if let Some(x) = bitset::find_zero(&*arena.commited()) {
+unsafe fn new_page<B: Tuned>(arena: &Arena<B>, class: usize) -> *const Page {
+    let slab = B::slab_size(class);
+    let pages = slab / B::pagesize();
+    let index = if pages == 1 {
+        bitset::find_zero(&*arena.commited())
+    } else {
+        bitset::find_zero_run(&*arena.commited(), pages)
+    };
+    let index = if let Some(x) = index {
         x
     } else {
         return ptr::null_mut();
     };
 
-    let pagesize = B::pagesize();
-
-    let page = (arena as *const Arena<B> as *const u8).add(index * pagesize) as *mut Page;
-
-    if !B::mcommit(page as _, pagesize) {
+    let addr = (arena as *const Arena<B> as *mut u8).add(index * B::pagesize());
+    if !Arena::commit_meta(arena, index, pages)
+        || !reserve::commit::<B>(Arena::header(arena), addr, slab)
+    {
         return ptr::null_mut();
     }
-    bitset::set(&mut *arena.commited(), index);
+    bitset::set_range(&mut *arena.commited(), index, pages);
+    Arena::map(arena, index, pages);
 
     *arena.rc.get() += 1;
 
-    (*page).rc = AtomicUsize::new(1);
+    // The entry may hold the metadata of an earlier slab or block.
+    let page = Arena::page(arena, index) as *mut Page;
     (*page).p.class = class as isize;
+    (*page).next = UnsafeCell::new(ptr::null());
+    (*page).prev = UnsafeCell::new(ptr::null());
+    (*page).rc = AtomicUsize::new(0);
 
-    let mut ptr = page.add(1) as *mut u8;
-    ptr = ptr.add(ptr.align_offset(size.next_power_of_two() / 2));
-    let vacancy = (pagesize - (ptr.add(size) as usize - page as usize)) / size;
+    let size = B::SMALL_CLASSES[class];
+    let zeroed = first_slot::<B>(page);
+    let vacancy = slab / size;
     (*page).vacancy = AtomicUsize::new(vacancy);
+    (*page).free = AtomicPtr::new(ptr::null_mut());
+    (*page).zeroed = UnsafeCell::new(zeroed);
+    if hardened::ENABLED {
+        (*page).secret = hardened::next(&mut *arena.rng.get()) as usize;
+        (*page).shuffle(arena, vacancy, size);
+    }
 
-    let mut free = ptr.add(size);
-    free = free.add(free.align_offset(mem::size_of::<usize>()));
-    (*page).free = AtomicPtr::new(free);
+    (*page).add_to_vacant(arena, class);
 
-    if vacancy > 0 {
-        (*page).add_to_vacant(arena, class);
+    page
+}
+
+/// # Safety
+///
+/// Pointer must be valid.
+///
+/// Lock must be locked.
+pub(super) unsafe fn alloc<B: Tuned>(arena: &Arena<B>, size: usize, zeroed: bool) -> *mut u8 {
+    let class = B::class_of(size);
+
+    let mut page = *arena.vacant[class].get();
+    if page.is_null() {
+        page = new_page(arena, class);
+        if page.is_null() {
+            return ptr::null_mut();
+        }
     }
 
-    ptr
+    if !zeroed {
+        let ptr = (*page).alloc_from_free(arena, class);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        (*page).alloc_from_zeroed(arena, class)
+    } else {
+        let ptr = (*page).alloc_from_zeroed(arena, class);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        let ptr = (*page).alloc_from_free(arena, class);
+        if !ptr.is_null() {
+            ptr.write_bytes(0, B::SMALL_CLASSES[class]);
+        }
+        ptr
+    }
 }
 
-pub(super) fn realloc_in_place(class: isize, size: usize) -> bool {
+/// Fill `out` with slots of `class`, returning how many were allocated.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+///
+/// Lock must be locked.
+pub(super) unsafe fn alloc_batch<B: Tuned>(
+    arena: &Arena<B>,
+    class: usize,
+    out: &mut [*mut u8],
+) -> usize {
+    let mut n = 0;
+    while n < out.len() {
+        let mut page = *arena.vacant[class].get();
+        if page.is_null() {
+            page = new_page(arena, class);
+            if page.is_null() {
+                break;
+            }
+        }
+
+        n += (*page).alloc_many(arena, class, &mut out[n..]);
+    }
+    n
+}
+
+/// Call `f` with each live slot of a small page, returning the number of
+/// pages of its slab.
+///
+/// Slots in the cache of a thread, or waiting in a remote queue, are
+/// reported as live.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+///
+/// Lock must be locked.
+pub(super) unsafe fn walk<B: Tuned>(
+    page: *const Page,
+    arena: &Arena<B>,
+    f: &mut impl FnMut(&BlockInfo),
+) -> usize {
+    // Enough for slabs of 64 KiB with the smallest class.
+    const MAX_SLOTS: usize = 8 * 1024;
+
+    let class = (*page).p.class as usize;
+    let size = B::SMALL_CLASSES[class];
+    let first = first_slot::<B>(page);
+    let len = (*(*page).zeroed.get() as usize - first as usize) / size;
+
+    // Slots can be pushed to the free list without the lock, but not
+    // popped, so the list after its head is stable.
+    let mut free = [0usize; MAX_SLOTS / (8 * core::mem::size_of::<usize>())];
+    let mut x = (*page).free.load(Ordering::Acquire);
+    while !x.is_null() {
+        let index = (x as usize - first as usize) / size;
+        if index < MAX_SLOTS {
+            bitset::set(&mut free, index);
+        }
+        x = (*page).next::<B>(x);
+    }
+
+    for index in 0..len {
+        if index < MAX_SLOTS && bitset::get(&free, index) {
+            continue;
+        }
+        f(&BlockInfo {
+            ptr: first.add(index * size),
+            size,
+            kind: BlockKind::Small,
+            arena: arena as *const Arena<B> as *const u8,
+        });
+    }
+    B::slab_size(class) / B::pagesize()
+}
+
+/// Abort unless `ptr` is a live slot of a small page of `class`.
+///
+/// Only the last freed slot of the page and the cached slots of the thread
+/// are looked at, unless in debug mode.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+///
+/// Lock must be unlocked.
+#[inline]
+pub(super) unsafe fn check_free<B: Tuned>(
+    page: *const Page,
+    arena: &Arena<B>,
+    ptr: *mut u8,
+    class: usize,
+) {
+    // A page without live slots may be decommited, with stale metadata.
+    if (*page).rc.load(Ordering::Relaxed) == 0 {
+        check::double_free::<B>(ptr, format_args!("no live slot in its page"));
+    }
+
+    let size = B::SMALL_CLASSES[class];
+    let first = first_slot::<B>(page);
+    let offset = (ptr as usize).wrapping_sub(first as usize);
+    if !offset.is_multiple_of(size) || offset + size > B::slab_size(class) {
+        check::invalid_free::<B>(ptr, format_args!("not a slot of small class {}", size));
+    }
+
+    if (*page).free.load(Ordering::Relaxed) == ptr || tcache::contains(ptr, class) {
+        check::double_free::<B>(ptr, format_args!("slot of small class {} is free", size));
+    }
+
+    if B::DEBUG {
+        let _guard = arena.lock.lock();
+        if ptr >= *(*page).zeroed.get() {
+            check::invalid_free::<B>(ptr, format_args!("slot of small class {} is unused", size));
+        }
+        // Slots can be pushed to the free list without the lock, but not
+        // popped, so the list after its head is stable.
+        let mut x = (*page).free.load(Ordering::Acquire);
+        while !x.is_null() {
+            if x == ptr {
+                check::double_free::<B>(ptr, format_args!("slot of small class {} is free", size));
+            }
+            x = (*page).next::<B>(x);
+        }
+    }
+}
+
+/// Returns whether `ptr` is a slot handed out by `page`.
+///
+/// # Safety
+///
+/// Pointer must be valid.
+///
+/// Lock must be locked.
+pub(super) unsafe fn is_slot<B: Tuned>(page: *const Page, ptr: *mut u8) -> bool {
+    let class = (*page).p.class;
+    if class < 0 || class as usize >= B::SMALL_CLASSES.len() {
+        return false;
+    }
+    let size = B::SMALL_CLASSES[class as usize];
+    let offset = (ptr as usize).wrapping_sub(first_slot::<B>(page) as usize);
+    offset.is_multiple_of(size) && ptr < *(*page).zeroed.get()
+}
+
+pub(super) fn realloc_in_place<B: Tuned>(class: isize, size: usize) -> bool {
     let class = class as usize;
-    SMALL_CLASSES[class] >= size
+    B::SMALL_CLASSES[class] >= size
         && class
             .checked_sub(1)
-            .map_or(true, |prev| SMALL_CLASSES[prev] < size)
+            .is_none_or(|prev| B::SMALL_CLASSES[prev] < size)
 }
 
 /// # Safety
@@ -230,23 +581,37 @@ pub(super) fn realloc_in_place(class: isize, size: usize) -> bool {
 /// Pointer must be valid.
 ///
 /// Lock must be unlocked.
-pub(super) unsafe fn dealloc<B: Backend>(
+pub(super) unsafe fn dealloc<B: Tuned>(
     page: *const Page,
     arena: *const Arena<B>,
     x: *mut u8,
     class: isize,
 ) {
-    let mut next = (*page).free.load(Ordering::Relaxed);
-    loop {
-        *(x as *mut *mut u8) = next;
-        match (*page)
-            .free
-            .compare_exchange_weak(next, x, Ordering::Release, Ordering::Relaxed)
-        {
-            Ok(_) => break,
-            Err(e) => next = e,
-        }
+    (*page).push_free(x, x);
+    Page::reduce_rc(page, arena, class as usize, 1)
+}
+
+/// Deallocate slots that are all in the same page.
+///
+/// # Safety
+///
+/// Pointers must be valid.
+///
+/// Lock must be unlocked.
+pub(super) unsafe fn dealloc_many<B: Tuned>(
+    page: *const Page,
+    arena: *const Arena<B>,
+    xs: &[*mut u8],
+    class: isize,
+) {
+    let (head, tail) = match (xs.first(), xs.last()) {
+        (Some(head), Some(tail)) => (*head, *tail),
+        _ => return,
+    };
+    for x in xs.windows(2) {
+        (*page).set_next(x[0], x[1]);
     }
 
-    Page::reduce_rc(page, arena, class as usize)
+    (*page).push_free(head, tail);
+    Page::reduce_rc(page, arena, class as usize, xs.len())
 }
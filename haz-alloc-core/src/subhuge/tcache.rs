@@ -0,0 +1,144 @@
+use super::{dealloc_batch, Arena};
+use crate::config::{Tuned, MAX_CLASSES};
+use crate::reserve::ReserveHeader;
+use core::cell::UnsafeCell;
+use core::sync::atomic::Ordering;
+use core::{mem, ptr};
+
+// Slots cached per class
+const CACHE_SIZE: usize = 32;
+// Slots moved at once between the cache and the pages
+const BATCH: usize = CACHE_SIZE / 2;
+
+#[derive(Clone, Copy)]
+struct Bin {
+    len: usize,
+    // Slots allocated from the bin less those deallocated to it, not yet
+    // counted in the statistics of the arena
+    live: isize,
+    slots: [*mut u8; CACHE_SIZE],
+}
+
+impl Bin {
+    const EMPTY: Self = Self {
+        len: 0,
+        live: 0,
+        slots: [ptr::null_mut(); CACHE_SIZE],
+    };
+
+    /// Count the live slots of the bin in the statistics of `arena`, and
+    /// `n` slots more.
+    #[inline]
+    unsafe fn fold<B: Tuned>(&mut self, arena: *const Arena<B>, class: usize, n: isize) {
+        let n = mem::take(&mut self.live) + n;
+        if n != 0 {
+            (*arena).small[class].fetch_add(n as usize, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Free slots of the arena of a thread, to allocate and deallocate without
+/// taking its lock.
+///
+/// Cached slots still count as allocated for their page, and are not
+/// counted in the statistics of their arena. Slots allocated from or freed
+/// to the cache are counted there when their bin is refilled or flushed.
+struct TCache {
+    bins: UnsafeCell<[Bin; MAX_CLASSES]>,
+}
+
+#[thread_local]
+static TCACHE: TCache = TCache {
+    bins: UnsafeCell::new([Bin::EMPTY; MAX_CLASSES]),
+};
+
+/// Take a slot of `class` from the cache, refilling it from `arena` if it
+/// is empty.
+///
+/// # Safety
+///
+/// Arena must be the arena of the thread.
+#[inline]
+pub(super) unsafe fn alloc<B: Tuned>(arena: *const Arena<B>, class: usize) -> *mut u8 {
+    let bin = &mut (*TCACHE.bins.get())[class];
+    if bin.len == 0 {
+        let n = (*arena).alloc_batch(class, &mut bin.slots[..BATCH]);
+        if n == 0 {
+            return ptr::null_mut();
+        }
+        // The batch was counted as allocated, but the slots are cached.
+        bin.fold(arena, class, -(n as isize));
+        bin.len = n;
+    }
+
+    bin.len -= 1;
+    bin.live += 1;
+    bin.slots[bin.len]
+}
+
+/// Put a slot of `class` in the cache, flushing part of it if it is full.
+///
+/// # Safety
+///
+/// Pointer must be valid and in the arena of the thread.
+#[inline]
+pub(super) unsafe fn dealloc<B: Tuned>(arena: *const Arena<B>, ptr: *mut u8, class: usize) {
+    let bin = &mut (*TCACHE.bins.get())[class];
+    if bin.len == CACHE_SIZE {
+        flush_bin(arena, bin, class, BATCH);
+    }
+
+    bin.live -= 1;
+    bin.slots[bin.len] = ptr;
+    bin.len += 1;
+}
+
+/// Returns whether `ptr` is in the cache of the thread for `class`.
+#[inline]
+pub(super) fn contains(ptr: *mut u8, class: usize) -> bool {
+    let bin = unsafe { &(*TCACHE.bins.get())[class] };
+    bin.slots[..bin.len].contains(&ptr)
+}
+
+/// Return all cached slots to their pages.
+///
+/// # Safety
+///
+/// Arena must be the arena of the thread. Must not be called while the
+/// cache is in use.
+pub(super) unsafe fn flush<B: Tuned>(arena: *const Arena<B>) {
+    for (class, bin) in (*TCACHE.bins.get()).iter_mut().enumerate() {
+        flush_bin(arena, bin, class, bin.len);
+    }
+}
+
+/// Count the slots allocated from or freed to the cache in the statistics
+/// of the arena, leaving the slots cached.
+///
+/// # Safety
+///
+/// Arena must be the arena of the thread.
+pub(super) unsafe fn fold<B: Tuned>(arena: *const Arena<B>) {
+    for (class, bin) in (*TCACHE.bins.get()).iter_mut().enumerate() {
+        bin.fold(arena, class, 0);
+    }
+}
+
+/// Return the `n` oldest slots of `bin` to their pages.
+#[cold]
+unsafe fn flush_bin<B: Tuned>(arena: *const Arena<B>, bin: &mut Bin, class: usize, n: usize) {
+    // Freeing the slots uncounts them again.
+    bin.fold(arena, class, n as isize);
+
+    let slots = &mut bin.slots[..n];
+    // Sort so that slots of the same page are freed together.
+    slots.sort_unstable();
+    let mut rest = &slots[..];
+    while !rest.is_empty() {
+        let len = dealloc_batch::<B>(arena as *const ReserveHeader, rest);
+        rest = &rest[len..];
+    }
+
+    bin.slots.copy_within(n..bin.len, 0);
+    bin.len -= n;
+}
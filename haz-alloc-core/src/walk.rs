@@ -0,0 +1,23 @@
+/// Kind of a block, depending on its size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    /// A slot of a small page.
+    Small,
+    /// A run of pages of an arena.
+    Large,
+    /// A reservation of its own.
+    Huge,
+}
+
+/// A live block, as found by `walk`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockInfo {
+    /// Start of the block.
+    pub ptr: *mut u8,
+    /// Usable size of the block.
+    pub size: usize,
+    /// Kind of the block.
+    pub kind: BlockKind,
+    /// Start of the arena holding the block, or null for huge blocks.
+    pub arena: *const u8,
+}
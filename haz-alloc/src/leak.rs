@@ -0,0 +1,49 @@
+use crate::sys_common::{self, Stderr};
+use crate::{sys, Alloc, Config, NoHooks};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+static INIT: AtomicBool = AtomicBool::new(false);
+// Address of the report for the config of the allocator, or 0 until the
+// first thread allocates
+static REPORT: AtomicUsize = AtomicUsize::new(0);
+
+/// Enable or disable the report of blocks never freed at exit.
+///
+/// The report is also enabled if the `HAZ_ALLOC_LEAK_REPORT` environment
+/// variable is set to anything other than `0`, or by the `leak_report`
+/// option of `HAZ_ALLOC_CONF`, when the first thread allocates.
+pub fn set_leak_report(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if enabled && !REGISTERED.swap(true, Ordering::Relaxed) {
+        sys::atexit(report);
+    }
+}
+
+/// Read the environment once, on the first allocation of a thread, and keep
+/// the report for the config `C` of the allocator.
+pub(crate) fn init<C: Config>() {
+    if INIT.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    REPORT.store(report_in::<C> as fn() as usize, Ordering::Relaxed);
+    if sys::env_flag(b"HAZ_ALLOC_LEAK_REPORT\0") || sys_common::config().leak_report {
+        set_leak_report(true);
+    }
+}
+
+extern "C" fn report() {
+    let f = REPORT.load(Ordering::Relaxed);
+    // Nothing was allocated if no thread is attached.
+    if ENABLED.load(Ordering::Relaxed) && f != 0 {
+        unsafe { mem::transmute::<usize, fn()>(f)() };
+    }
+}
+
+fn report_in<C: Config>() {
+    // Every allocator of the process uses the same config.
+    let alloc = unsafe { Alloc::<NoHooks, C>::with_config() };
+    let _ = alloc.leak_report(&mut Stderr);
+}
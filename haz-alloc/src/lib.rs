@@ -1,31 +1,99 @@
-#![feature(linkage)]
 #![warn(clippy::all)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 
+#[cfg(feature = "allocator_api")]
+use std::alloc::{AllocError, Allocator};
 use std::alloc::{GlobalAlloc, Layout};
+use std::fmt;
+#[cfg(feature = "allocator_api")]
+use std::ptr::NonNull;
 
+#[cfg(feature = "leak_report")]
+mod leak;
+#[cfg(feature = "profiling")]
+mod profile;
 mod sys;
 mod sys_common;
 
-#[derive(Clone, Copy)]
-pub struct Alloc {
-    alloc: haz_alloc_core::Alloc<sys::Backend>,
+pub use haz_alloc_core::{
+    AllocHooks, ArenaStats, BlockInfo, BlockKind, Config, DefaultConfig, NoHooks, RuntimeConfig,
+    SizeClass, Stats,
+};
+#[cfg(feature = "leak_report")]
+pub use leak::set_leak_report;
+#[cfg(feature = "profiling")]
+pub use profile::{dump_profile, set_profile_rate};
+
+/// The allocator, calling the hooks `H` on each allocation, deallocation
+/// and reallocation, and tuned by the config `C`.
+pub struct Alloc<H = NoHooks, C = DefaultConfig> {
+    alloc: haz_alloc_core::Alloc<sys::Backend<C>, H, C>,
+}
+
+impl<H, C> Copy for Alloc<H, C> {}
+
+impl<H, C> Clone for Alloc<H, C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
 impl Alloc {
     pub const fn new() -> Self {
+        Self::with_hooks()
+    }
+}
+
+impl<H> Alloc<H> {
+    /// Create a new `Alloc` calling the hooks `H`.
+    pub const fn with_hooks() -> Self {
         Alloc {
-            alloc: unsafe { haz_alloc_core::Alloc::new() },
+            alloc: unsafe { haz_alloc_core::Alloc::with_hooks() },
         }
     }
 }
 
-impl Alloc {
+impl<H, C: Config> Alloc<H, C> {
+    /// Create a new `Alloc` calling the hooks `H` and tuned by the config
+    /// `C`.
+    ///
+    /// # Safety
+    ///
+    /// Every `Alloc` and `Heap` of the process must use the same config.
+    ///
+    /// A config out of range fails the build:
+    ///
+    /// ```compile_fail,E0080
+    /// use haz_alloc::{Alloc, Config, NoHooks};
+    ///
+    /// struct Cramped;
+    ///
+    /// impl Config for Cramped {
+    ///     // No room for the metadata of an arena and 64 pages of blocks.
+    ///     const RESERVE_ALIGN: usize = 256 * 1024;
+    ///     const HUGE_THRESHOLD: usize = 32 * 1024;
+    /// }
+    ///
+    /// static ALLOC: Alloc<NoHooks, Cramped> = unsafe { Alloc::with_config() };
+    /// ```
+    pub const unsafe fn with_config() -> Self {
+        Alloc {
+            alloc: haz_alloc_core::Alloc::with_config(),
+        }
+    }
+}
+
+impl<H: AllocHooks, C: Config> Alloc<H, C> {
     /// # Safety
     ///
     /// Layout must be valid.
     #[inline]
     pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.alloc.alloc(layout)
+        let ptr = self.alloc.alloc(layout);
+        #[cfg(feature = "profiling")]
+        profile::on_alloc(ptr, layout.size());
+        ptr
     }
 
     /// # Safety
@@ -33,7 +101,10 @@ impl Alloc {
     /// Layout must be valid.
     #[inline]
     pub unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        self.alloc.alloc_zeroed(layout)
+        let ptr = self.alloc.alloc_zeroed(layout);
+        #[cfg(feature = "profiling")]
+        profile::on_alloc(ptr, layout.size());
+        ptr
     }
 
     /// # Safety
@@ -43,7 +114,41 @@ impl Alloc {
     /// Alignment must match of original allocation.
     #[inline]
     pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
-        self.alloc.realloc(ptr, layout)
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr);
+        let ptr = self.alloc.realloc(ptr, layout);
+        #[cfg(feature = "profiling")]
+        profile::on_alloc(ptr, layout.size());
+        ptr
+    }
+
+    /// Grow a block without moving it, returning the new usable size, or
+    /// `None` if there is no room to grow in place.
+    ///
+    /// A block that already fits `new_layout` is left as is.
+    ///
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub unsafe fn try_grow_in_place(&self, ptr: *mut u8, new_layout: Layout) -> Option<usize> {
+        self.alloc.try_grow_in_place(ptr, new_layout)
+    }
+
+    /// Shrink a block without moving it, returning the new usable size, or
+    /// `None` if it cannot be shrunk in place or `new_layout` is larger than
+    /// the block, in which case the block still has its original layout.
+    ///
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub unsafe fn shrink_in_place(&self, ptr: *mut u8, new_layout: Layout) -> Option<usize> {
+        self.alloc.shrink_in_place(ptr, new_layout)
     }
 
     /// # Safety
@@ -51,9 +156,57 @@ impl Alloc {
     /// Pointer must be valid.
     #[inline]
     pub unsafe fn dealloc(&self, ptr: *mut u8) {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr);
         self.alloc.dealloc(ptr)
     }
 
+    /// Deallocate using the layout of the block to skip loading its metadata
+    /// where possible.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Alignment must match of original allocation, and size must be between
+    /// the requested size and the usable size.
+    #[inline]
+    pub unsafe fn dealloc_sized(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr);
+        self.alloc.dealloc_sized(ptr, layout)
+    }
+
+    /// Allocate blocks for `layout` into `out`, returning how many were
+    /// allocated before running out of memory.
+    ///
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    #[inline]
+    pub unsafe fn alloc_batch(&self, layout: Layout, out: &mut [*mut u8]) -> usize {
+        let n = self.alloc.alloc_batch(layout, out);
+        #[cfg(feature = "profiling")]
+        for &ptr in &out[..n] {
+            profile::on_alloc(ptr, layout.size());
+        }
+        n
+    }
+
+    /// Deallocate all blocks in `ptrs`.
+    ///
+    /// # Safety
+    ///
+    /// Pointers must be valid.
+    #[inline]
+    pub unsafe fn dealloc_batch(&self, ptrs: &[*mut u8]) {
+        #[cfg(feature = "profiling")]
+        for &ptr in ptrs {
+            profile::on_free(ptr);
+        }
+        self.alloc.dealloc_batch(ptrs)
+    }
+
     /// # Safety
     ///
     /// Pointer must be valid.
@@ -61,17 +214,128 @@ impl Alloc {
     pub unsafe fn size(&self, ptr: *mut u8) -> usize {
         self.alloc.size(ptr)
     }
+
+    /// Returns the usable size of the block that would be allocated for
+    /// `layout`, without allocating.
+    #[inline]
+    pub fn good_size(&self, layout: Layout) -> usize {
+        self.alloc.good_size(layout)
+    }
+
+    /// Release the cached arenas of exited threads that have no block left,
+    /// returning the bytes given back to the system.
+    ///
+    /// The quarantine is emptied first.
+    #[inline]
+    pub fn purge(&self) -> usize {
+        self.alloc.purge()
+    }
+
+    /// Give up the arena of the current thread, as when the thread exits,
+    /// returning the bytes given back to the system.
+    ///
+    /// Meant for threads going idle. The thread takes an arena again on its
+    /// next allocation. The quarantine is emptied first.
+    #[inline]
+    pub fn flush(&self) -> usize {
+        self.alloc.flush()
+    }
+
+    /// Call `f` with each live block, including the blocks of every heap.
+    ///
+    /// The cache of the current thread and the quarantine are flushed
+    /// first, but blocks in the cache of other threads, or freed by a thread
+    /// other than the owner of their arena and not yet taken back, are
+    /// reported as live.
+    ///
+    /// `f` must not allocate or deallocate, as arenas cannot be created or
+    /// released until it returns.
+    #[inline]
+    pub fn walk(&self, f: impl FnMut(&BlockInfo)) {
+        self.alloc.walk(f)
+    }
+
+    /// Write a report of the live blocks, grouped by arena and size class,
+    /// returning how many there are.
+    ///
+    /// Nothing is written if there is no live block. Like `walk`, blocks in
+    /// the cache of other threads are reported as live, and `w` must not
+    /// allocate or deallocate.
+    #[inline]
+    pub fn leak_report(&self, w: &mut impl fmt::Write) -> Result<usize, fmt::Error> {
+        self.alloc.leak_report(w)
+    }
+
+    /// Returns the statistics of the allocator, including the blocks of
+    /// every heap. Blocks in the quarantine count as live. Small blocks
+    /// allocated or deallocated through the cache of another thread may not
+    /// be counted yet.
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        self.alloc.stats()
+    }
+
+    /// Call `f` with the statistics of each arena.
+    ///
+    /// `f` must not allocate or deallocate, as arenas cannot be created or
+    /// released until it returns.
+    #[inline]
+    pub fn arena_stats(&self, f: impl FnMut(&ArenaStats)) {
+        self.alloc.arena_stats(f)
+    }
+
+    /// Set the most bytes of freed blocks held by the quarantine, in place
+    /// of `RuntimeConfig::quarantine`. Blocks larger than it are never held.
+    #[cfg(feature = "quarantine")]
+    #[inline]
+    pub fn set_quarantine_size(&self, bytes: usize) {
+        self.alloc.set_quarantine_size(bytes)
+    }
+
+    /// Set the most bytes committed at once by every arena and huge block,
+    /// or lift the limit if `None`.
+    ///
+    /// Allocations needing to commit beyond the limit call the OOM handler,
+    /// and return null unless it asks for a retry. A limit below the bytes
+    /// already committed only fails the next commits.
+    #[inline]
+    pub fn set_memory_limit(&self, bytes: Option<usize>) {
+        self.alloc.set_memory_limit(bytes)
+    }
+
+    /// Set the function called with the layout of an allocation that
+    /// failed, or remove it if `None`.
+    ///
+    /// It may free memory and return `true` to retry the allocation, at most
+    /// 4 times, after which the allocation returns null. It is not called
+    /// again for the allocations it makes.
+    #[inline]
+    pub fn set_oom_handler(&self, f: Option<fn(Layout) -> bool>) {
+        self.alloc.set_oom_handler(f)
+    }
+
+    /// Returns the runtime configuration, parsed once from the
+    /// `HAZ_ALLOC_CONF` environment variable.
+    ///
+    /// The variable holds a list of `key:value` separated by commas, such as
+    /// `arenas:4,huge_threshold:64k,purge_on_exit:true`. Invalid options are
+    /// reported to the standard error and ignored. See `RuntimeConfig` for
+    /// the keys.
+    #[inline]
+    pub fn config(&self) -> &'static RuntimeConfig {
+        sys_common::config()
+    }
 }
 
-unsafe impl GlobalAlloc for Alloc {
+unsafe impl<H: AllocHooks, C: Config> GlobalAlloc for Alloc<H, C> {
     #[inline]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         self.alloc(layout)
     }
 
     #[inline]
-    unsafe fn dealloc(&self, ptr: *mut u8, _: Layout) {
-        self.dealloc(ptr)
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_sized(ptr, layout)
     }
 
     #[inline]
@@ -88,8 +352,247 @@ unsafe impl GlobalAlloc for Alloc {
     }
 }
 
-impl Default for Alloc {
+#[cfg(feature = "allocator_api")]
+unsafe impl<H: AllocHooks, C: Config> Allocator for Alloc<H, C> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.alloc.allocate(layout);
+        #[cfg(feature = "profiling")]
+        if let Ok(block) = block {
+            profile::on_alloc(block.as_ptr() as _, layout.size());
+        }
+        block
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let block = self.alloc.allocate_zeroed(layout);
+        #[cfg(feature = "profiling")]
+        if let Ok(block) = block {
+            profile::on_alloc(block.as_ptr() as _, layout.size());
+        }
+        block
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr.as_ptr());
+        self.alloc.deallocate(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr.as_ptr());
+        let block = self.alloc.grow(ptr, old_layout, new_layout);
+        #[cfg(feature = "profiling")]
+        if let Ok(block) = block {
+            profile::on_alloc(block.as_ptr() as _, new_layout.size());
+        }
+        block
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr.as_ptr());
+        let block = self.alloc.grow_zeroed(ptr, old_layout, new_layout);
+        #[cfg(feature = "profiling")]
+        if let Ok(block) = block {
+            profile::on_alloc(block.as_ptr() as _, new_layout.size());
+        }
+        block
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        #[cfg(feature = "profiling")]
+        profile::on_free(ptr.as_ptr());
+        let block = self.alloc.shrink(ptr, old_layout, new_layout);
+        #[cfg(feature = "profiling")]
+        if let Ok(block) = block {
+            profile::on_alloc(block.as_ptr() as _, new_layout.size());
+        }
+        block
+    }
+}
+
+impl<H> Default for Alloc<H> {
     fn default() -> Self {
-        Alloc::new()
+        Alloc::with_hooks()
+    }
+}
+
+/// A private heap, whose blocks are all released when it is dropped.
+///
+/// Blocks of a heap must only be deallocated or reallocated by the same
+/// heap.
+pub struct Heap<C: Config = DefaultConfig> {
+    heap: haz_alloc_core::Heap<sys::Backend<C>, C>,
+}
+
+impl Heap {
+    /// Create a new `Heap`, or `None` if there is no memory for it.
+    pub fn new() -> Option<Self> {
+        unsafe { Self::with_config() }
+    }
+}
+
+impl<C: Config> Heap<C> {
+    /// Create a new `Heap` tuned by the config `C`, or `None` if there is
+    /// no memory for it.
+    ///
+    /// # Safety
+    ///
+    /// Every `Alloc` and `Heap` of the process must use the same config.
+    pub unsafe fn with_config() -> Option<Self> {
+        Some(Heap {
+            heap: haz_alloc_core::Heap::new()?,
+        })
+    }
+
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    #[inline]
+    pub unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc(layout)
+    }
+
+    /// # Safety
+    ///
+    /// Layout must be valid.
+    #[inline]
+    pub unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.heap.alloc_zeroed(layout)
+    }
+
+    /// # Safety
+    ///
+    /// Layout and pointer must be valid.
+    ///
+    /// Alignment must match of original allocation.
+    #[inline]
+    pub unsafe fn realloc(&self, ptr: *mut u8, layout: Layout) -> *mut u8 {
+        self.heap.realloc(ptr, layout)
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    pub unsafe fn dealloc(&self, ptr: *mut u8) {
+        self.heap.dealloc(ptr)
+    }
+
+    /// Deallocate using the layout of the block to skip loading its metadata
+    /// where possible.
+    ///
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    ///
+    /// Alignment must match of original allocation, and size must be between
+    /// the requested size and the usable size.
+    #[inline]
+    pub unsafe fn dealloc_sized(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc_sized(ptr, layout)
+    }
+
+    /// # Safety
+    ///
+    /// Pointer must be valid.
+    #[inline]
+    pub unsafe fn size(&self, ptr: *mut u8) -> usize {
+        self.heap.size(ptr)
+    }
+}
+
+unsafe impl<C: Config> GlobalAlloc for Heap<C> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dealloc_sized(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.realloc(
+            ptr,
+            Layout::from_size_align_unchecked(new_size, layout.align()),
+        )
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+unsafe impl<C: Config> Allocator for Heap<C> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.allocate(layout)
+    }
+
+    #[inline]
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.allocate_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.heap.deallocate(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.grow(ptr, old_layout, new_layout)
+    }
+
+    #[inline]
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    #[inline]
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.heap.shrink(ptr, old_layout, new_layout)
     }
 }
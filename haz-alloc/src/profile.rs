@@ -0,0 +1,264 @@
+//! Sampling heap profiler.
+//!
+//! Every `rate` bytes allocated on average, the stack of the allocation is
+//! captured and the block is put in a table until it is freed. The table is
+//! dumped in the legacy heap profile format of gperftools, which `pprof`
+//! reads.
+//!
+//! Blocks of heaps are not sampled, and blocks resized in place keep the
+//! size they were sampled with.
+
+use crate::{sys, sys_common};
+use std::cell::{Cell, UnsafeCell};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+// Most frames of a stack
+const DEPTH: usize = 32;
+// Frames of the profiler itself, skipped
+const SKIP: usize = 2;
+// Slots of the table
+const CAPACITY: usize = 4096;
+// Slots probed from the hash of a pointer
+const PROBES: usize = 8;
+
+// Keys of slots not holding a sample
+const EMPTY: usize = 0;
+const TOMBSTONE: usize = 1;
+
+// Mean bytes between samples, or 0 if disabled
+static RATE: AtomicUsize = AtomicUsize::new(0);
+// Samples in the table
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+// Mixed into the seed of every thread
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+// Set once the configuration is read
+static INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+struct Sample {
+    size: usize,
+    depth: usize,
+    frames: [usize; DEPTH],
+}
+
+struct Table {
+    // Taken to fill a slot or read the samples, but not to empty a slot
+    lock: Mutex<()>,
+    // Pointer of the block sampled in each slot
+    keys: [AtomicUsize; CAPACITY],
+    samples: [UnsafeCell<Sample>; CAPACITY],
+}
+
+unsafe impl Sync for Table {}
+
+static TABLE: Table = Table {
+    lock: Mutex::new(()),
+    keys: [const { AtomicUsize::new(EMPTY) }; CAPACITY],
+    samples: [const {
+        UnsafeCell::new(Sample {
+            size: 0,
+            depth: 0,
+            frames: [0; DEPTH],
+        })
+    }; CAPACITY],
+};
+
+struct Local {
+    // Bytes left before the next sample, or 0 if not drawn yet
+    left: Cell<usize>,
+    rng: Cell<u64>,
+    // Set while the thread is sampling, as capturing a stack may allocate
+    busy: Cell<bool>,
+}
+
+thread_local! {
+    static LOCAL: Local = const {
+        Local {
+            left: Cell::new(0),
+            rng: Cell::new(0),
+            busy: Cell::new(false),
+        }
+    };
+}
+
+/// Sample an allocation every `bytes` allocated on average, or stop sampling
+/// if `0`.
+///
+/// Sampling is disabled by default, unless set by the `profile_rate` option
+/// of `HAZ_ALLOC_CONF`. Blocks already sampled stay in the profile until
+/// freed.
+pub fn set_profile_rate(bytes: usize) {
+    RATE.store(bytes, Ordering::Relaxed);
+}
+
+/// Read the configuration once, on the first allocation of a thread.
+pub(crate) fn init() {
+    let rate = sys_common::config().profile_rate;
+    if !INIT.swap(true, Ordering::Relaxed) && rate != 0 {
+        set_profile_rate(rate);
+    }
+}
+
+/// Write a profile of the sampled blocks still live, in the legacy heap
+/// profile format of gperftools.
+///
+/// Samples are scaled by `pprof` with the current rate. Only blocks still
+/// live are tracked, so the allocated totals are the live ones.
+pub fn dump_profile(w: &mut impl Write) -> io::Result<()> {
+    let mut samples = Vec::with_capacity(CAPACITY);
+    {
+        let _guard = TABLE.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        for (key, sample) in TABLE.keys.iter().zip(&TABLE.samples) {
+            if !matches!(key.load(Ordering::Acquire), EMPTY | TOMBSTONE) {
+                samples.push(unsafe { *sample.get() });
+            }
+        }
+    }
+    samples.sort_unstable_by(|a, b| a.frames[..a.depth].cmp(&b.frames[..b.depth]));
+
+    // Group the samples by stack
+    let mut stacks: Vec<(usize, usize, &[usize])> = Vec::new();
+    for sample in &samples {
+        let frames = &sample.frames[..sample.depth];
+        match stacks.last_mut() {
+            Some((count, bytes, last)) if *last == frames => {
+                *count += 1;
+                *bytes += sample.size;
+            }
+            _ => stacks.push((1, sample.size, frames)),
+        }
+    }
+
+    let bytes: usize = samples.iter().map(|x| x.size).sum();
+    writeln!(
+        w,
+        "heap profile: {}: {} [{}: {}] @ heap_v2/{}",
+        samples.len(),
+        bytes,
+        samples.len(),
+        bytes,
+        RATE.load(Ordering::Relaxed).max(1)
+    )?;
+    for (count, bytes, frames) in stacks {
+        write!(w, "{}: {} [{}: {}] @", count, bytes, count, bytes)?;
+        for frame in frames {
+            write!(w, " {:#x}", frame)?;
+        }
+        writeln!(w)?;
+    }
+
+    // Needed by `pprof` to symbolize the stacks
+    #[cfg(target_os = "linux")]
+    if let Ok(maps) = std::fs::read("/proc/self/maps") {
+        writeln!(w, "\nMAPPED_LIBRARIES:")?;
+        w.write_all(&maps)?;
+    }
+    Ok(())
+}
+
+/// Count an allocation of `size` bytes at `ptr`, sampling it if due.
+#[inline]
+pub(crate) fn on_alloc(ptr: *mut u8, size: usize) {
+    let rate = RATE.load(Ordering::Relaxed);
+    if rate == 0 || ptr.is_null() {
+        return;
+    }
+
+    let _ = LOCAL.try_with(|local| {
+        let mut left = local.left.get();
+        if left == 0 {
+            left = interval(local, rate);
+        }
+        if left > size {
+            local.left.set(left - size);
+            return;
+        }
+
+        local.left.set(interval(local, rate));
+        if !local.busy.replace(true) {
+            sample(ptr, size);
+            local.busy.set(false);
+        }
+    });
+}
+
+/// Remove the block at `ptr` from the table, if sampled.
+///
+/// Must be called before the block is freed, so that its address is not
+/// sampled again meanwhile.
+#[inline]
+pub(crate) fn on_free(ptr: *mut u8) {
+    if LIVE.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    for i in probes(ptr) {
+        let key = &TABLE.keys[i];
+        if key.load(Ordering::Relaxed) == ptr as usize
+            && key
+                .compare_exchange(
+                    ptr as usize,
+                    TOMBSTONE,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            LIVE.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// Capture the stack and put the block in the table. The sample is dropped
+/// if every slot probed is taken.
+#[inline(never)]
+fn sample(ptr: *mut u8, size: usize) {
+    let mut frames = [0; SKIP + DEPTH];
+    let depth = sys::backtrace(&mut frames).saturating_sub(SKIP);
+    let mut sample = Sample {
+        size,
+        depth,
+        frames: [0; DEPTH],
+    };
+    sample.frames[..depth].copy_from_slice(&frames[SKIP..SKIP + depth]);
+
+    let _guard = TABLE.lock.lock().unwrap_or_else(PoisonError::into_inner);
+    for i in probes(ptr) {
+        if matches!(TABLE.keys[i].load(Ordering::Relaxed), EMPTY | TOMBSTONE) {
+            unsafe { *TABLE.samples[i].get() = sample };
+            TABLE.keys[i].store(ptr as usize, Ordering::Release);
+            LIVE.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    }
+}
+
+/// Returns the slots where the block at `ptr` may be.
+fn probes(ptr: *mut u8) -> impl Iterator<Item = usize> {
+    let hash = (ptr as usize >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15_u64 as usize);
+    let first = hash >> (usize::BITS - CAPACITY.trailing_zeros());
+    (first..first + PROBES).map(|i| i % CAPACITY)
+}
+
+/// Returns the bytes to allocate before the next sample, drawn from an
+/// exponential distribution of mean `rate`.
+fn interval(local: &Local, rate: usize) -> usize {
+    let mut x = local.rng.get();
+    if x == 0 {
+        x = local as *const Local as usize as u64 ^ COUNTER.fetch_add(1, Ordering::Relaxed) as u64;
+    }
+    // SplitMix64
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    local.rng.set(x);
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+
+    // Uniform in (0, 1]
+    let u = ((z >> 11) + 1) as f64 / (1u64 << 53) as f64;
+    (-u.ln() * rate as f64) as usize + 1
+}
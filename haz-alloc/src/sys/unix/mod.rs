@@ -1,14 +1,18 @@
 use self::mutex::Mutex;
 use crate::sys_common;
-use core::ptr;
 use core::sync::atomic::{AtomicUsize, Ordering};
+use core::marker::PhantomData;
+use core::{fmt, ptr};
 use haz_alloc_core::backend::TlsCallback;
+use haz_alloc_core::{Config, RuntimeConfig};
+use std::ffi::CStr;
 
 mod mutex;
 
-pub struct Backend;
+/// The backend of the allocators tuned by the config `C`.
+pub struct Backend<C>(PhantomData<C>);
 
-unsafe impl haz_alloc_core::Backend for Backend {
+unsafe impl<C: Config> haz_alloc_core::Backend for Backend<C> {
     type Mutex = Mutex;
 
     fn mreserve(ptr: *mut u8, size: usize) -> *mut u8 {
@@ -69,6 +73,88 @@ unsafe impl haz_alloc_core::Backend for Backend {
     }
 
     unsafe fn tls_attach(callback: *const TlsCallback) {
-        sys_common::tls_attach(callback)
+        sys_common::tls_attach::<C>(callback)
     }
+
+    fn abort(args: fmt::Arguments<'_>) -> ! {
+        sys_common::abort(args)
+    }
+
+    #[inline]
+    fn config() -> &'static RuntimeConfig {
+        sys_common::config()
+    }
+
+    fn random(buf: &mut [u8]) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))] {
+                let mut rest = buf;
+                while !rest.is_empty() {
+                    let n = unsafe {
+                        libc::getrandom(rest.as_mut_ptr() as _, rest.len(), libc::GRND_NONBLOCK)
+                    };
+                    if n <= 0 {
+                        return false;
+                    }
+                    rest = &mut rest[n as usize..];
+                }
+                true
+            } else if #[cfg(any(target_os = "macos", target_os = "openbsd"))] {
+                // At most 256 bytes are read at once.
+                buf.chunks_mut(256)
+                    .all(|x| unsafe { libc::getentropy(x.as_mut_ptr() as _, x.len()) } == 0)
+            } else {
+                let _ = buf;
+                false
+            }
+        }
+    }
+}
+
+/// Call `f` with the value of the environment variable `name`, if set.
+///
+/// `name` must be nul-terminated.
+pub fn with_env<T>(name: &[u8], f: impl FnOnce(Option<&[u8]>) -> T) -> T {
+    let value = unsafe { libc::getenv(name.as_ptr() as _) };
+    f((!value.is_null()).then(|| unsafe { CStr::from_ptr(value) }.to_bytes()))
+}
+
+/// Returns whether the environment variable `name` is set and not `0`.
+///
+/// `name` must be nul-terminated.
+#[cfg(feature = "leak_report")]
+pub fn env_flag(name: &[u8]) -> bool {
+    let value = unsafe { libc::getenv(name.as_ptr() as _) };
+    !value.is_null() && unsafe { !matches!(*value as u8, 0 | b'0') }
+}
+
+pub fn write_stderr(mut buf: &[u8]) {
+    while !buf.is_empty() {
+        let n = unsafe { libc::write(2, buf.as_ptr() as _, buf.len()) };
+        if n <= 0 {
+            break;
+        }
+        buf = &buf[n as usize..];
+    }
+}
+
+/// Write the return addresses of the stack into `frames`, starting from
+/// this function, returning how many were written.
+#[cfg(feature = "profiling")]
+#[inline(never)]
+pub fn backtrace(frames: &mut [usize]) -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos"))] {
+            let n = unsafe { libc::backtrace(frames.as_mut_ptr() as _, frames.len() as _) };
+            n.max(0) as usize
+        } else {
+            let _ = frames;
+            0
+        }
+    }
+}
+
+#[cfg(feature = "leak_report")]
+pub fn atexit(f: extern "C" fn()) {
+    unsafe { libc::atexit(f) };
 }
@@ -1,4 +1,5 @@
 use self::functions::{futex_wait, futex_wake};
+use crate::sys_common;
 use core::hint;
 use core::sync::atomic::{AtomicU32, Ordering};
 
@@ -50,7 +51,7 @@ impl Mutex {
     }
 
     fn spin(&self) -> u32 {
-        let mut spin = 100;
+        let mut spin = sys_common::config().spin;
         loop {
             let state = self.futex.load(Ordering::Relaxed);
 
@@ -1,7 +1,11 @@
 use crate::sys_common;
 use haz_alloc_core::backend::TlsCallback;
+use haz_alloc_core::{Config, RuntimeConfig};
 use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
 use std::mem::MaybeUninit;
+use std::ptr;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
 use winapi::um::memoryapi::*;
@@ -39,9 +43,10 @@ impl Drop for MutexGuard<'_> {
     }
 }
 
-pub struct Backend;
+/// The backend of the allocators tuned by the config `C`.
+pub struct Backend<C>(PhantomData<C>);
 
-unsafe impl haz_alloc_core::Backend for Backend {
+unsafe impl<C: Config> haz_alloc_core::Backend for Backend<C> {
     type Mutex = Mutex;
 
     fn mreserve(ptr: *mut u8, size: usize) -> *mut u8 {
@@ -85,6 +90,108 @@ unsafe impl haz_alloc_core::Backend for Backend {
     }
 
     unsafe fn tls_attach(callback: *const TlsCallback) {
-        sys_common::tls_attach(callback)
+        sys_common::tls_attach::<C>(callback)
     }
+
+    fn abort(args: fmt::Arguments<'_>) -> ! {
+        sys_common::abort(args)
+    }
+
+    #[inline]
+    fn config() -> &'static RuntimeConfig {
+        sys_common::config()
+    }
+
+    fn random(buf: &mut [u8]) -> bool {
+        use winapi::um::bcrypt::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG};
+
+        buf.chunks_mut(u32::MAX as usize).all(|x| unsafe {
+            BCryptGenRandom(
+                ptr::null_mut(),
+                x.as_mut_ptr(),
+                x.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            ) >= 0
+        })
+    }
+}
+
+/// Call `f` with the value of the environment variable `name`, if set.
+///
+/// `name` must be nul-terminated. Values over 1023 bytes are taken as unset.
+pub fn with_env<T>(name: &[u8], f: impl FnOnce(Option<&[u8]>) -> T) -> T {
+    use winapi::um::processenv::GetEnvironmentVariableA;
+
+    let mut value = [0u8; 1024];
+    let n = unsafe {
+        GetEnvironmentVariableA(
+            name.as_ptr() as _,
+            value.as_mut_ptr() as _,
+            value.len() as _,
+        )
+    } as usize;
+    f((n > 0 && n < value.len()).then(|| &value[..n]))
+}
+
+/// Returns whether the environment variable `name` is set and not `0`.
+///
+/// `name` must be nul-terminated.
+#[cfg(feature = "leak_report")]
+pub fn env_flag(name: &[u8]) -> bool {
+    use winapi::um::processenv::GetEnvironmentVariableA;
+
+    let mut value = [0u8; 2];
+    let n = unsafe {
+        GetEnvironmentVariableA(
+            name.as_ptr() as _,
+            value.as_mut_ptr() as _,
+            value.len() as _,
+        )
+    };
+    n > 0 && !(n == 1 && value[0] == b'0')
+}
+
+pub fn write_stderr(mut buf: &[u8]) {
+    use winapi::um::fileapi::WriteFile;
+    use winapi::um::processenv::GetStdHandle;
+    use winapi::um::winbase::STD_ERROR_HANDLE;
+
+    let handle = unsafe { GetStdHandle(STD_ERROR_HANDLE) };
+    while !buf.is_empty() {
+        let mut n = 0;
+        let ok = unsafe {
+            WriteFile(
+                handle,
+                buf.as_ptr() as _,
+                buf.len() as _,
+                &mut n,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 || n == 0 {
+            break;
+        }
+        buf = &buf[n as usize..];
+    }
+}
+
+/// Write the return addresses of the stack into `frames`, starting from
+/// this function, returning how many were written.
+#[cfg(feature = "profiling")]
+#[inline(never)]
+pub fn backtrace(frames: &mut [usize]) -> usize {
+    let len = frames.len().min(u16::MAX as usize);
+    unsafe {
+        RtlCaptureStackBackTrace(0, len as _, frames.as_mut_ptr() as _, std::ptr::null_mut())
+            as usize
+    }
+}
+
+#[cfg(feature = "leak_report")]
+pub fn atexit(f: extern "C" fn()) {
+    extern "C" {
+        fn atexit(f: extern "C" fn()) -> i32;
+    }
+
+    unsafe { atexit(f) };
 }
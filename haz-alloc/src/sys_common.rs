@@ -1,9 +1,13 @@
+use crate::sys;
 use haz_alloc_core::backend::TlsCallback;
-use std::cell::Cell;
-use std::ptr;
+use haz_alloc_core::RuntimeConfig;
+use std::cell::{Cell, UnsafeCell};
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::{hint, process, ptr, str};
 
 thread_local! {
-    static ATTACHED: Attached = Attached(Cell::new(ptr::null()));
+    static ATTACHED: Attached = const { Attached(Cell::new(ptr::null())) };
 }
 
 struct Attached(Cell<*const TlsCallback>);
@@ -23,8 +27,87 @@ impl Drop for Attached {
     }
 }
 
-pub unsafe fn tls_attach(callback: *const TlsCallback) {
+// The config is only needed for the leak report.
+#[cfg_attr(
+    not(feature = "leak_report"),
+    allow(clippy::extra_unused_type_parameters)
+)]
+pub unsafe fn tls_attach<C: haz_alloc_core::Config>(callback: *const TlsCallback) {
+    #[cfg(feature = "leak_report")]
+    crate::leak::init::<C>();
+    #[cfg(feature = "profiling")]
+    crate::profile::init();
+
     ATTACHED.with(|attached| {
         attached.0.set(callback);
     });
 }
+
+/// Standard error, written to without allocating.
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        sys::write_stderr(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn abort(args: fmt::Arguments<'_>) -> ! {
+    let _ = writeln!(Stderr, "{}", args);
+    process::abort()
+}
+
+const UNINIT: u8 = 0;
+const BUSY: u8 = 1;
+const READY: u8 = 2;
+
+struct Config {
+    state: AtomicU8,
+    config: UnsafeCell<RuntimeConfig>,
+}
+
+unsafe impl Sync for Config {}
+
+static CONFIG: Config = Config {
+    state: AtomicU8::new(UNINIT),
+    config: UnsafeCell::new(RuntimeConfig::DEFAULT),
+};
+
+/// Returns the configuration, parsed from the `HAZ_ALLOC_CONF` environment
+/// variable on first use.
+#[inline]
+pub fn config() -> &'static RuntimeConfig {
+    if CONFIG.state.load(Ordering::Acquire) != READY {
+        init_config();
+    }
+    unsafe { &*CONFIG.config.get() }
+}
+
+#[cold]
+fn init_config() {
+    if CONFIG
+        .state
+        .compare_exchange(UNINIT, BUSY, Ordering::Acquire, Ordering::Acquire)
+        .is_err()
+    {
+        while CONFIG.state.load(Ordering::Acquire) != READY {
+            hint::spin_loop();
+        }
+        return;
+    }
+
+    // Nothing here allocates, as it runs from the first allocation.
+    sys::with_env(b"HAZ_ALLOC_CONF\0", |conf| {
+        let config = unsafe { &mut *CONFIG.config.get() };
+        if let Err(option) = config.apply(conf.unwrap_or_default()) {
+            let option = str::from_utf8(option).unwrap_or("?");
+            let _ = writeln!(
+                Stderr,
+                "haz-alloc: invalid option `{}` in HAZ_ALLOC_CONF",
+                option
+            );
+        }
+    });
+    CONFIG.state.store(READY, Ordering::Release);
+}
@@ -0,0 +1,49 @@
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[cfg(target_pointer_width = "64")]
+const ALIGNS: &[usize] = &[64 * 1024 * 1024, 256 * 1024 * 1024, 1024 * 1024 * 1024];
+#[cfg(not(target_pointer_width = "64"))]
+const ALIGNS: &[usize] = &[4 * 1024 * 1024, 16 * 1024 * 1024];
+
+#[test]
+fn test_huge_align() {
+    unsafe {
+        for align in ALIGNS {
+            for size in [8, 4096, 3276800] {
+                let layout = Layout::from_size_align(size, *align).unwrap();
+                let p = ALLOC.alloc_zeroed(layout) as *mut u64;
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0);
+                assert_eq!(*p, 0);
+                assert!(ALLOC.size(p as _) >= size);
+                *p = 100;
+                ALLOC.dealloc(p as _);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_huge_align_realloc() {
+    unsafe {
+        for align in ALIGNS {
+            let mut p = ALLOC.alloc(Layout::from_size_align(16, *align).unwrap()) as *mut u64;
+            assert!(!p.is_null());
+            *p = 100;
+            p = ALLOC.realloc(p as _, Layout::from_size_align(3276800, *align).unwrap())
+                as *mut u64;
+            assert_eq!(p as usize % align, 0);
+            assert_eq!(*p, 100);
+            assert!(ALLOC.size(p as _) >= 3276800);
+            *(p as *mut u8).add(3276799) = 1;
+            p = ALLOC.realloc(p as _, Layout::from_size_align(4096, *align).unwrap()) as *mut u64;
+            assert_eq!(p as usize % align, 0);
+            assert_eq!(*p, 100);
+            assert!(ALLOC.size(p as _) >= 4096);
+            ALLOC.dealloc(p as _);
+        }
+    }
+}
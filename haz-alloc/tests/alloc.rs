@@ -1,5 +1,4 @@
-use haz_alloc::Alloc;
-use haz_alloc_core::__internal::SMALL_CLASSES;
+use haz_alloc::{Alloc, SizeClass};
 use std::alloc::Layout;
 
 static ALLOC: Alloc = Alloc::new();
@@ -8,11 +7,94 @@ static ALLOC: Alloc = Alloc::new();
 fn test_small() {
     unsafe {
         // classes
-        for size0 in SMALL_CLASSES {
-            let p = ALLOC.alloc_zeroed(Layout::from_size_align(*size0, 8).unwrap()) as *mut u64;
+        for class in SizeClass::all() {
+            let size0 = class.size();
+            let p = ALLOC.alloc_zeroed(Layout::from_size_align(size0, 8).unwrap()) as *mut u64;
             assert_eq!(*p, 0);
-            assert_eq!(ALLOC.size(p as _), *size0);
+            assert_eq!(ALLOC.size(p as _), size0);
             ALLOC.dealloc(p as _);
         }
     }
 }
+
+#[test]
+fn test_small_slots() {
+    unsafe {
+        for class in SizeClass::all() {
+            for align in [1, 8, 16, 32] {
+                let layout = Layout::from_size_align(class.size(), align).unwrap();
+                let ptrs: Vec<_> = (0..64).map(|_| ALLOC.alloc(layout)).collect();
+                for (i, p) in ptrs.iter().enumerate() {
+                    assert_eq!(*p as usize % align, 0);
+                    p.write_bytes(i as u8, ALLOC.size(*p));
+                }
+                for (i, p) in ptrs.iter().enumerate() {
+                    let slice = std::slice::from_raw_parts(*p, ALLOC.size(*p));
+                    assert!(slice.iter().all(|x| *x == i as u8));
+                    ALLOC.dealloc(*p);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_slabs() {
+    unsafe {
+        // These classes use slabs of several pages. Blocks in every page of
+        // a slab are freed and reused, in any order.
+        for size in [1792, 2048, 3584] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let mut ptrs: Vec<_> = (0..100).map(|_| ALLOC.alloc(layout)).collect();
+            for round in 0..2 {
+                for (i, p) in ptrs.iter().enumerate() {
+                    assert!(ALLOC.size(*p) >= size);
+                    p.write_bytes((i + round) as u8, size);
+                }
+                for (i, p) in ptrs.iter().enumerate() {
+                    let slice = std::slice::from_raw_parts(*p, size);
+                    assert!(slice.iter().all(|x| *x == (i + round) as u8));
+                }
+                for p in ptrs.iter_mut().skip(round).step_by(2) {
+                    ALLOC.dealloc(*p);
+                    *p = ALLOC.alloc(layout);
+                }
+            }
+            for p in ptrs.into_iter().rev() {
+                ALLOC.dealloc_sized(p, layout);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dealloc_sized() {
+    unsafe {
+        for size in [1, 8, 100, 3584, 3585, 10240, 262144, 262145, 3276800] {
+            for align in [8, 64, 8192] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let p = ALLOC.alloc(layout);
+                ALLOC.dealloc_sized(p, layout);
+
+                // Any size up to the usable size fits the block.
+                let p = ALLOC.alloc(layout);
+                let usable = Layout::from_size_align(ALLOC.size(p), align).unwrap();
+                ALLOC.dealloc_sized(p, usable);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_good_size() {
+    unsafe {
+        for size in (0..4096).chain([10240, 262144, 262145, 3276800]) {
+            for align in [1, 8, 64, 4096, 8192] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let p = ALLOC.alloc(layout);
+                assert_eq!(ALLOC.size(p), ALLOC.good_size(layout));
+                ALLOC.dealloc(p);
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "allocator_api")]
+#![feature(allocator_api)]
+
+use haz_alloc::Alloc;
+use std::alloc::{Allocator, Layout};
+
+static ALLOC: Alloc = Alloc::new();
+
+#[test]
+fn test_collections() {
+    let mut vec = Vec::new_in(ALLOC);
+    for i in 0..100000 {
+        vec.push(i);
+    }
+    for (i, x) in vec.iter().enumerate() {
+        assert_eq!(*x, i);
+    }
+    vec.truncate(10);
+    vec.shrink_to_fit();
+    assert_eq!(vec, (0..10).collect::<Vec<_>>());
+
+    let boxed = Box::new_in([7u8; 1000], ALLOC);
+    assert!(boxed.iter().all(|x| *x == 7));
+}
+
+#[test]
+fn test_usable_size() {
+    unsafe {
+        for size in [1, 24, 100, 1000, 3000, 10240, 327680] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let block = ALLOC.allocate(layout).unwrap();
+            assert!(block.len() >= size);
+            assert_eq!(block.len(), ALLOC.size(block.as_ptr() as _));
+            // The whole slack is usable.
+            (block.as_ptr() as *mut u8).write_bytes(0xff, block.len());
+            ALLOC.deallocate(block.cast(), layout);
+        }
+    }
+}
+
+#[test]
+fn test_grow_shrink() {
+    unsafe {
+        let old = Layout::from_size_align(327680, 8).unwrap();
+        let block = ALLOC.allocate_zeroed(old).unwrap();
+        (block.as_ptr() as *mut u8).write_bytes(1, old.size());
+
+        let new = Layout::from_size_align(3276800, 8).unwrap();
+        let block = ALLOC.grow_zeroed(block.cast(), old, new).unwrap();
+        assert!(block.len() >= new.size());
+        let slice = &*block.as_ptr();
+        assert!(slice[..old.size()].iter().all(|x| *x == 1));
+        assert!(slice[old.size()..].iter().all(|x| *x == 0));
+
+        // Huge blocks shrink in place.
+        let small = Layout::from_size_align(400000, 8).unwrap();
+        let shrunk = ALLOC.shrink(block.cast(), new, small).unwrap();
+        assert_eq!(shrunk.as_ptr() as *mut u8, block.as_ptr() as *mut u8);
+        assert!(shrunk.len() >= small.size());
+        let slice = &*shrunk.as_ptr();
+        assert!(slice[..old.size()].iter().all(|x| *x == 1));
+        assert!(slice[old.size()..small.size()].iter().all(|x| *x == 0));
+
+        ALLOC.deallocate(shrunk.cast(), small);
+    }
+}
@@ -0,0 +1,84 @@
+use haz_alloc::{Alloc, SizeClass};
+use std::alloc::Layout;
+use std::ptr;
+use std::thread;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[test]
+fn test_batch() {
+    unsafe {
+        for class in SizeClass::all() {
+            let layout = Layout::from_size_align(class.size(), 8).unwrap();
+            let mut ptrs = vec![ptr::null_mut(); 3000];
+            assert_eq!(ALLOC.alloc_batch(layout, &mut ptrs), ptrs.len());
+            for (i, p) in ptrs.iter().enumerate() {
+                assert_eq!(ALLOC.size(*p), class.size());
+                p.write_bytes(i as u8, class.size());
+            }
+            for (i, p) in ptrs.iter().enumerate() {
+                let slice = std::slice::from_raw_parts(*p, class.size());
+                assert!(slice.iter().all(|x| *x == i as u8));
+            }
+
+            // Free half, then allocate again to reuse the free lists.
+            ALLOC.dealloc_batch(&ptrs[..1500]);
+            assert_eq!(ALLOC.alloc_batch(layout, &mut ptrs[..1500]), 1500);
+            ALLOC.dealloc_batch(&ptrs);
+        }
+    }
+}
+
+#[test]
+fn test_batch_mixed() {
+    unsafe {
+        let mut ptrs = Vec::new();
+        for size in [8, 100, 10240, 8, 3276800, 1000, 8] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let mut batch = [ptr::null_mut(); 4];
+            assert_eq!(ALLOC.alloc_batch(layout, &mut batch), 4);
+            ptrs.extend_from_slice(&batch);
+        }
+        ALLOC.dealloc_batch(&ptrs);
+    }
+}
+
+#[test]
+fn test_batch_remote() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut ptrs = vec![ptr::null_mut(); 10000];
+    unsafe {
+        assert_eq!(ALLOC.alloc_batch(layout, &mut ptrs), ptrs.len());
+    }
+    let ptrs = ptrs.into_iter().map(|x| x as usize).collect::<Vec<_>>();
+    thread::spawn(move || unsafe {
+        let ptrs = ptrs.into_iter().map(|x| x as *mut u8).collect::<Vec<_>>();
+        ALLOC.dealloc_batch(&ptrs);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_batch_interleaved() {
+    unsafe {
+        // Blocks of two classes, in an order where no two neighbours share a
+        // page.
+        let mut a = [ptr::null_mut(); 100];
+        let mut b = [ptr::null_mut(); 100];
+        assert_eq!(
+            ALLOC.alloc_batch(Layout::from_size_align(48, 8).unwrap(), &mut a),
+            100
+        );
+        assert_eq!(
+            ALLOC.alloc_batch(Layout::from_size_align(20000, 8).unwrap(), &mut b),
+            100
+        );
+        let mut ptrs = Vec::new();
+        for (x, y) in a.iter().rev().zip(&b) {
+            ptrs.push(*x);
+            ptrs.push(*y);
+        }
+        ALLOC.dealloc_batch(&ptrs);
+    }
+}
@@ -0,0 +1,88 @@
+use haz_alloc::{Alloc, Heap};
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+static ALLOC: Alloc = Alloc::new();
+
+const MIB: usize = 1024 * 1024;
+
+// Block freed by the OOM handler
+static CACHE: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn oom_handler(layout: Layout) -> bool {
+    CALLS.fetch_add(1, Ordering::Relaxed);
+    // Allocating from the handler does not call it again.
+    assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+
+    let cache = CACHE.swap(std::ptr::null_mut(), Ordering::Relaxed);
+    if cache.is_null() {
+        return false;
+    }
+    unsafe { ALLOC.dealloc(cache) };
+    true
+}
+
+// Asks for retries without freeing anything
+fn stubborn_handler(_: Layout) -> bool {
+    CALLS.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+// The limit is global, so everything is checked from a single test.
+#[test]
+fn test_memory_limit() {
+    // Blocks in the quarantine stay committed.
+    #[cfg(feature = "quarantine")]
+    ALLOC.set_quarantine_size(0);
+
+    unsafe {
+        let layout = Layout::from_size_align(8 * MIB, 8).unwrap();
+
+        // A huge block commits each of its pages once, the header included.
+        let before = ALLOC.stats().committed;
+        let p = ALLOC.alloc(layout);
+        // In debug mode, blocks are padded with a redzone and a trailer.
+        let padding = if cfg!(feature = "debug") { 24 } else { 0 };
+        let pages = (p as usize % 4096 + 8 * MIB + padding).div_ceil(4096);
+        assert_eq!(ALLOC.stats().committed, before + pages * 4096);
+        ALLOC.dealloc(p);
+        assert_eq!(ALLOC.stats().committed, before);
+
+        let limit = ALLOC.stats().committed + 12 * MIB;
+        ALLOC.set_memory_limit(Some(limit));
+
+        // Commits beyond the limit fail.
+        let p = ALLOC.alloc(layout);
+        assert!(!p.is_null());
+        assert!(ALLOC.alloc(layout).is_null());
+        assert!(ALLOC.stats().committed <= limit);
+
+        // The handler frees memory and asks for a retry.
+        ALLOC.set_oom_handler(Some(oom_handler));
+        CACHE.store(p, Ordering::Relaxed);
+        let p = ALLOC.alloc(layout);
+        assert!(!p.is_null());
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+        // Nothing left to free.
+        assert!(ALLOC.alloc(layout).is_null());
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+
+        // Retries are bounded, in heaps too.
+        ALLOC.set_oom_handler(Some(stubborn_handler));
+        assert!(ALLOC.alloc(layout).is_null());
+        assert_eq!(CALLS.load(Ordering::Relaxed), 6);
+        let heap = Heap::new().unwrap();
+        assert!(heap.alloc(layout).is_null());
+        assert_eq!(CALLS.load(Ordering::Relaxed), 10);
+        drop(heap);
+
+        ALLOC.set_oom_handler(None);
+        ALLOC.set_memory_limit(None);
+        let q = ALLOC.alloc(layout);
+        assert!(!q.is_null());
+        ALLOC.dealloc(p);
+        ALLOC.dealloc(q);
+    }
+}
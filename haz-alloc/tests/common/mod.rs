@@ -0,0 +1,14 @@
+use std::process::{Command, ExitStatus};
+
+/// Run the test `test` of this binary alone in a child process, with the
+/// environment variables `env`, returning its status and standard error.
+///
+/// One of `env` tells the test that it runs as the child.
+pub fn run_child(test: &str, env: &[(&str, &str)]) -> (ExitStatus, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", test, "--test-threads=1"])
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    (output.status, String::from_utf8(output.stderr).unwrap())
+}
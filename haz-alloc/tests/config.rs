@@ -0,0 +1,70 @@
+mod common;
+
+use haz_alloc::{Alloc, BlockKind, RuntimeConfig};
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+fn kind(ptr: *mut u8) -> Option<BlockKind> {
+    let mut kind = None;
+    ALLOC.walk(|block| {
+        if block.ptr == ptr {
+            kind = Some(block.kind);
+        }
+    });
+    kind
+}
+
+#[test]
+fn test_config() {
+    if std::env::var_os("HAZ_ALLOC_CONF_CHILD").is_none() {
+        let (status, stderr) = common::run_child(
+            "test_config",
+            &[
+                ("HAZ_ALLOC_CONF_CHILD", "1"),
+                (
+                    "HAZ_ALLOC_CONF",
+                    "huge_threshold:64k,bogus,purge_on_exit:1,spin:7",
+                ),
+            ],
+        );
+        assert!(status.success(), "{}", stderr);
+        assert!(
+            stderr.contains("haz-alloc: invalid option `bogus` in HAZ_ALLOC_CONF\n"),
+            "{}",
+            stderr
+        );
+        return;
+    }
+
+    assert_eq!(
+        *ALLOC.config(),
+        RuntimeConfig {
+            huge_threshold: 64 * 1024,
+            purge_on_exit: true,
+            spin: 7,
+            ..RuntimeConfig::DEFAULT
+        }
+    );
+
+    unsafe {
+        let large = ALLOC.alloc(Layout::from_size_align(60000, 8).unwrap());
+        let huge = ALLOC.alloc(Layout::from_size_align(70000, 8).unwrap());
+        assert_eq!(kind(large), Some(BlockKind::Large));
+        assert_eq!(kind(huge), Some(BlockKind::Huge));
+        ALLOC.dealloc(large);
+        ALLOC.dealloc(huge);
+    }
+
+    // The arena of the thread is released as it exits, as it has no block.
+    #[cfg(feature = "quarantine")]
+    ALLOC.set_quarantine_size(0);
+    std::thread::spawn(|| unsafe {
+        ALLOC.dealloc(ALLOC.alloc(Layout::from_size_align(48, 8).unwrap()));
+    })
+    .join()
+    .unwrap();
+    let mut parked = 0;
+    ALLOC.arena_stats(|arena| parked += arena.parked as usize);
+    assert_eq!(parked, 0);
+}
@@ -0,0 +1,85 @@
+#[cfg(feature = "leak_report")]
+mod common;
+
+use haz_alloc::{Alloc, BlockKind, Config, NoHooks, SizeClass};
+use std::alloc::Layout;
+
+struct Tiny;
+
+impl Config for Tiny {
+    const RESERVE_ALIGN: usize = 4 * 1024 * 1024;
+    const HUGE_THRESHOLD: usize = 64 * 1024;
+    const SMALL_CLASSES: &'static [usize] = &[16, 32, 64, 128, 256];
+    const ARENAS: usize = 2;
+}
+
+// Every allocator of the process must use the same config.
+static ALLOC: Alloc<NoHooks, Tiny> = unsafe { Alloc::with_config() };
+
+fn kind(ptr: *mut u8) -> Option<BlockKind> {
+    let mut kind = None;
+    ALLOC.walk(|block| {
+        if block.ptr == ptr {
+            kind = Some(block.kind);
+        }
+    });
+    kind
+}
+
+#[test]
+fn test_custom_config() {
+    let sizes: Vec<_> = SizeClass::all_in::<Tiny>().map(|x| x.size()).collect();
+    assert_eq!(sizes, [16, 32, 64, 128, 256]);
+
+    #[cfg(feature = "quarantine")]
+    ALLOC.set_quarantine_size(0);
+
+    unsafe {
+        // In debug mode, blocks are padded with a redzone and a trailer.
+        let padding = if cfg!(feature = "debug") { 24 } else { 0 };
+        let class = SizeClass::of_in::<Tiny>(100 + padding).unwrap();
+        assert_eq!(class.size(), 128);
+
+        let small = ALLOC.alloc(Layout::from_size_align(100, 8).unwrap());
+        let aligned = ALLOC.alloc(Layout::from_size_align(32, 32).unwrap());
+        let large = ALLOC.alloc(Layout::from_size_align(1000, 8).unwrap());
+        let huge = ALLOC.alloc(Layout::from_size_align(70000, 8).unwrap());
+        assert_eq!(kind(small), Some(BlockKind::Small));
+        assert_eq!(kind(aligned), Some(BlockKind::Small));
+        assert_eq!(kind(large), Some(BlockKind::Large));
+        assert_eq!(kind(huge), Some(BlockKind::Huge));
+        assert_eq!(aligned as usize % 32, 0);
+        assert_eq!(ALLOC.stats().small[class.index()], 128);
+
+        let small = ALLOC.realloc(small, Layout::from_size_align(200, 8).unwrap());
+        let huge = ALLOC.realloc(huge, Layout::from_size_align(200000, 8).unwrap());
+        assert_eq!(kind(small), Some(BlockKind::Small));
+        assert_eq!(kind(huge), Some(BlockKind::Huge));
+
+        for ptr in [small, aligned, large, huge] {
+            ALLOC.dealloc(ptr);
+        }
+    }
+    assert_eq!(ALLOC.stats().small_total(), 0);
+}
+
+#[cfg(feature = "leak_report")]
+#[test]
+fn test_leak_report_at_exit() {
+    if std::env::var_os("HAZ_ALLOC_LEAK_CHILD").is_some() {
+        // Leak from the child process.
+        unsafe { ALLOC.alloc(Layout::from_size_align(100, 8).unwrap()) };
+        return;
+    }
+
+    // The report walks the arenas with the config of the allocator.
+    let (status, stderr) = common::run_child(
+        "test_leak_report_at_exit",
+        &[
+            ("HAZ_ALLOC_LEAK_CHILD", "1"),
+            ("HAZ_ALLOC_LEAK_REPORT", "1"),
+        ],
+    );
+    assert!(status.success(), "{}", stderr);
+    assert!(stderr.contains("  small 128: 1 blocks"), "{}", stderr);
+}
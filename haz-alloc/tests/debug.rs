@@ -0,0 +1,95 @@
+#![cfg(feature = "debug")]
+
+mod common;
+
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+use std::slice;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[test]
+fn test_fill() {
+    unsafe {
+        for size in [1, 48, 1000, 100000] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let p = ALLOC.alloc(layout);
+            assert_eq!(ALLOC.size(p), size);
+            assert!(slice::from_raw_parts(p, size).iter().all(|x| *x == 0xAA));
+            ALLOC.dealloc(p);
+
+            let p = ALLOC.alloc_zeroed(layout);
+            assert!(slice::from_raw_parts(p, size).iter().all(|x| *x == 0));
+            ALLOC.dealloc_sized(p, layout);
+        }
+
+        // Keep a block live in the page, so that the freed slot stays
+        // readable. The first word may be reused to link the slot.
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let live = ALLOC.alloc(layout);
+        let p = ALLOC.alloc(layout);
+        ALLOC.dealloc(p);
+        assert!(slice::from_raw_parts(p.add(8), 40)
+            .iter()
+            .all(|x| *x == 0xDD));
+        ALLOC.dealloc(live);
+    }
+}
+
+#[test]
+fn test_realloc() {
+    unsafe {
+        let p = ALLOC.alloc(Layout::from_size_align(10, 1).unwrap());
+        p.write_bytes(1, 10);
+        let p = ALLOC.realloc(p, Layout::from_size_align(20, 1).unwrap());
+        assert_eq!(ALLOC.size(p), 20);
+        let block = slice::from_raw_parts(p, 20);
+        assert!(block[..10].iter().all(|x| *x == 1));
+        assert!(block[10..].iter().all(|x| *x == 0xAA));
+
+        let p = ALLOC.realloc(p, Layout::from_size_align(5, 1).unwrap());
+        assert_eq!(ALLOC.size(p), 5);
+        assert!(slice::from_raw_parts(p, 5).iter().all(|x| *x == 1));
+        ALLOC.dealloc(p);
+    }
+}
+
+#[test]
+fn test_corruption() {
+    if let Some(case) = std::env::var_os("HAZ_ALLOC_DEBUG_CHILD") {
+        // Corrupt a block from the child process.
+        unsafe {
+            match case.to_str().unwrap() {
+                "small" => {
+                    let p = ALLOC.alloc(Layout::from_size_align(48, 8).unwrap());
+                    p.add(48).write(0);
+                    ALLOC.dealloc(p);
+                }
+                "large" => {
+                    let layout = Layout::from_size_align(100000, 8).unwrap();
+                    let p = ALLOC.alloc(layout);
+                    p.add(100001).write(0);
+                    ALLOC.realloc(p, Layout::from_size_align(200000, 8).unwrap());
+                }
+                "size" => {
+                    let p = ALLOC.alloc(Layout::from_size_align(48, 8).unwrap());
+                    ALLOC.dealloc_sized(p, Layout::from_size_align(40, 8).unwrap());
+                }
+                _ => unreachable!(),
+            }
+        }
+        return;
+    }
+
+    for (case, message) in [
+        ("small", "corrupted at offset 48, in small class"),
+        ("large", "corrupted at offset 100001, in large block"),
+        ("size", "of size 48 deallocated with size 40"),
+    ] {
+        let (status, stderr) =
+            common::run_child("test_corruption", &[("HAZ_ALLOC_DEBUG_CHILD", case)]);
+        assert!(!status.success());
+        assert!(stderr.contains("haz-alloc: "), "{}", stderr);
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[cfg(target_pointer_width = "64")]
+const RESERVE_ALIGN: usize = 32 * 1024 * 1024;
+#[cfg(not(target_pointer_width = "64"))]
+const RESERVE_ALIGN: usize = 2 * 1024 * 1024;
+
+/// Run `case` of `test_bad_free` in a child process, returning its standard
+/// error after checking that it aborted.
+fn run(case: &str) -> String {
+    let (status, stderr) = common::run_child("test_bad_free", &[("HAZ_ALLOC_FREE_CHILD", case)]);
+    assert!(!status.success(), "{}", case);
+    stderr
+}
+
+#[test]
+fn test_bad_free() {
+    if let Some(case) = std::env::var_os("HAZ_ALLOC_FREE_CHILD") {
+        let small = Layout::from_size_align(48, 8).unwrap();
+        let large = Layout::from_size_align(100000, 8).unwrap();
+        let huge = Layout::from_size_align(64 * 1024 * 1024, 8).unwrap();
+        unsafe {
+            match case.to_str().unwrap() {
+                "small-double" => {
+                    let p = ALLOC.alloc(small);
+                    ALLOC.dealloc(p);
+                    ALLOC.dealloc(p);
+                }
+                "small-sized-double" => {
+                    let p = ALLOC.alloc(small);
+                    ALLOC.dealloc_sized(p, small);
+                    ALLOC.dealloc_sized(p, small);
+                }
+                "small-sized-mismatch" => {
+                    let p = ALLOC.alloc(small);
+                    ALLOC.dealloc_sized(p, large);
+                }
+                "small-interior" => {
+                    let p = ALLOC.alloc(small);
+                    ALLOC.dealloc(p.add(8));
+                }
+                "small-unused" => {
+                    // Past the slots taken by the cache of the thread, in
+                    // the class of the padded layout.
+                    let p = ALLOC.alloc(small);
+                    ALLOC.dealloc(p.add(80 * 18));
+                }
+                "small-listed" => {
+                    let p = ALLOC.alloc(small);
+                    let q = ALLOC.alloc(small);
+                    ALLOC.dealloc(p);
+                    ALLOC.dealloc(q);
+                    ALLOC.dealloc(p);
+                }
+                "large-double" => {
+                    let p = ALLOC.alloc(large);
+                    ALLOC.dealloc(p);
+                    ALLOC.dealloc(p);
+                }
+                "uncommitted" => {
+                    // The last page of the arena, never used by the child.
+                    let p = ALLOC.alloc(small);
+                    let arena = p as usize & !(RESERVE_ALIGN - 1);
+                    ALLOC.dealloc((arena + RESERVE_ALIGN - 4096) as *mut u8);
+                }
+                "large-interior" => {
+                    let p = ALLOC.alloc(large);
+                    ALLOC.dealloc(p.add(16));
+                }
+                "huge-interior" => {
+                    let p = ALLOC.alloc(huge);
+                    ALLOC.dealloc(p.add(16));
+                }
+                "huge-double" => {
+                    let p = ALLOC.alloc(huge);
+                    ALLOC.dealloc(p);
+                    ALLOC.dealloc(p);
+                }
+                _ => unreachable!(),
+            }
+        }
+        return;
+    }
+
+    for (case, message) in [
+        ("small-double", "double free of "),
+        ("small-sized-double", "double free of "),
+        ("small-interior", "not a slot of small class"),
+        ("small-listed", "double free of "),
+        ("large-double", "double free of "),
+        ("large-interior", "not the start of a large block"),
+        ("uncommitted", "not in a page of blocks"),
+        ("huge-interior", "not the start of a huge block"),
+    ] {
+        let stderr = run(case);
+        assert!(stderr.contains(message), "{}: {}", case, stderr);
+    }
+
+    // In debug mode, the size is reported instead.
+    if !cfg!(feature = "debug") {
+        let stderr = run("small-sized-mismatch");
+        assert!(stderr.contains("layout does not match"), "{}", stderr);
+    }
+
+    // These are only caught in debug mode.
+    if cfg!(feature = "debug") {
+        for (case, message) in [
+            ("small-unused", "is unused"),
+            ("huge-double", "not allocated by haz-alloc"),
+        ] {
+            // In hardened mode, every slot of a page is used from the start.
+            if cfg!(feature = "hardened") && case == "small-unused" {
+                continue;
+            }
+            let stderr = run(case);
+            assert!(stderr.contains(message), "{}: {}", case, stderr);
+        }
+    }
+}
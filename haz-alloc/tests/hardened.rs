@@ -0,0 +1,97 @@
+#![cfg(feature = "hardened")]
+
+mod common;
+
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+use std::thread;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[test]
+fn test_random_order() {
+    // A new thread takes a new arena, with no page of the class yet.
+    thread::spawn(|| unsafe {
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let mut blocks = [std::ptr::null_mut(); 40];
+        assert_eq!(ALLOC.alloc_batch(layout, &mut blocks), blocks.len());
+        assert!(!blocks.windows(2).all(|x| x[0] < x[1]));
+        // Slots are not visited by steps of a constant modulo the page.
+        let mut steps: Vec<_> = blocks
+            .windows(2)
+            .map(|x| x[1] as isize - x[0] as isize)
+            .collect();
+        steps.sort_unstable();
+        steps.dedup();
+        assert!(steps.len() > 2, "{:?}", steps);
+        ALLOC.dealloc_batch(&blocks);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_corrupted_link() {
+    if std::env::var_os("HAZ_ALLOC_HARDENED_CHILD").is_some() {
+        // Overwrite the link of a free slot from the child process.
+        #[cfg(feature = "quarantine")]
+        ALLOC.set_quarantine_size(0);
+        unsafe {
+            let layout = Layout::from_size_align(48, 8).unwrap();
+            let mut blocks = [std::ptr::null_mut(); 2];
+            ALLOC.alloc_batch(layout, &mut blocks);
+            ALLOC.dealloc_batch(&blocks[..1]);
+            // Blocks refused by the quarantine go to the cache of the
+            // thread.
+            #[cfg(feature = "quarantine")]
+            ALLOC.flush();
+            (blocks[0] as *mut usize).write(0x4141_4141);
+            ALLOC.alloc_batch(layout, &mut blocks[..1]);
+        }
+        return;
+    }
+
+    let (status, stderr) =
+        common::run_child("test_corrupted_link", &[("HAZ_ALLOC_HARDENED_CHILD", "1")]);
+    assert!(!status.success());
+    assert!(
+        stderr.contains("haz-alloc: corrupted free list"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_corrupted_remote_link() {
+    if std::env::var_os("HAZ_ALLOC_HARDENED_CHILD").is_some() {
+        // Overwrite the link of a slot in the remote queue of the arena of
+        // this thread, which is followed on the next allocation.
+        #[cfg(feature = "quarantine")]
+        ALLOC.set_quarantine_size(0);
+        unsafe {
+            let layout = Layout::from_size_align(48, 8).unwrap();
+            let blocks = [ALLOC.alloc(layout) as usize, ALLOC.alloc(layout) as usize];
+            thread::spawn(move || {
+                for p in blocks {
+                    ALLOC.dealloc(p as *mut u8);
+                }
+            })
+            .join()
+            .unwrap();
+            (blocks[1] as *mut usize).write(0x4141_4141);
+            ALLOC.alloc(layout);
+        }
+        return;
+    }
+
+    let (status, stderr) = common::run_child(
+        "test_corrupted_remote_link",
+        &[("HAZ_ALLOC_HARDENED_CHILD", "1")],
+    );
+    assert!(!status.success());
+    assert!(
+        stderr.contains("haz-alloc: corrupted remote queue"),
+        "{}",
+        stderr
+    );
+}
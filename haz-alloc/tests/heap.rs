@@ -0,0 +1,121 @@
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+use haz_alloc::Heap;
+use std::alloc::Layout;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn test_heap() {
+    unsafe {
+        let heap = Heap::new().unwrap();
+        let mut ptrs = Vec::new();
+        for size in [8, 100, 4096, 100000, 3276800] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            let p = heap.alloc_zeroed(layout);
+            assert!(!p.is_null());
+            assert!(heap.size(p) >= size);
+            assert_eq!(*p, 0);
+            assert_eq!(*p.add(size - 1), 0);
+            p.write_bytes(0xAB, size);
+            ptrs.push((p, layout));
+        }
+        for (p, layout) in ptrs {
+            assert_eq!(*p.add(layout.size() - 1), 0xAB);
+            heap.dealloc_sized(p, layout);
+        }
+    }
+}
+
+#[test]
+fn test_heap_realloc() {
+    unsafe {
+        let heap = Heap::new().unwrap();
+        let mut p = heap.alloc(Layout::from_size_align(16, 8).unwrap()) as *mut u64;
+        *p = 100;
+        for size in [1000, 100000, 3276800, 16] {
+            p = heap.realloc(p as _, Layout::from_size_align(size, 8).unwrap()) as *mut u64;
+            assert!(!p.is_null());
+            assert_eq!(*p, 100);
+            assert!(heap.size(p as _) >= size);
+        }
+        heap.dealloc(p as _);
+    }
+}
+
+#[test]
+fn test_heap_small_pages() {
+    // Small pages hold nothing but slots, so blocks of a power of two are
+    // packed from the start of the first page.
+    let padding = if cfg!(feature = "debug") { 24 } else { 0 };
+    let layout = Layout::from_size_align(1024 - padding, 8).unwrap();
+    unsafe {
+        let heap = Heap::new().unwrap();
+        let mut ptrs: Vec<_> = (0..64).map(|_| heap.alloc(layout) as usize).collect();
+        ptrs.sort_unstable();
+        assert_eq!(ptrs[0] % 4096, 0);
+        for (i, p) in ptrs.iter().enumerate() {
+            assert_eq!(*p, ptrs[0] + i * 1024);
+        }
+    }
+}
+
+#[test]
+fn test_heap_drop_live() {
+    // Dropping the heap releases everything still allocated from it.
+    for _ in 0..64 {
+        let heap = Heap::new().unwrap();
+        unsafe {
+            for size in [8, 1000, 100000, 3276800] {
+                for _ in 0..16 {
+                    let p = heap.alloc(Layout::from_size_align(size, 8).unwrap());
+                    assert!(!p.is_null());
+                    *p = 1;
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_heap_threads() {
+    let heap = Arc::new(Heap::new().unwrap());
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let heap = heap.clone();
+            thread::spawn(move || unsafe {
+                let layout = Layout::from_size_align(8 + i * 24, 8).unwrap();
+                let mut ptrs = Vec::new();
+                for j in 0..10000 {
+                    let p = heap.alloc(layout) as *mut usize;
+                    assert!(!p.is_null());
+                    *p = j;
+                    ptrs.push(p);
+                }
+                for (j, p) in ptrs.iter().enumerate() {
+                    assert_eq!(**p, j);
+                }
+                // Leave half of the blocks for the heap to release.
+                for p in &ptrs[..ptrs.len() / 2] {
+                    heap.dealloc(*p as _);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+#[test]
+fn test_heap_allocator() {
+    let heap = Heap::new().unwrap();
+    let mut v = Vec::new_in(&heap);
+    for i in 0..100000usize {
+        v.push(i);
+    }
+    assert!(v.iter().enumerate().all(|(i, x)| i == *x));
+    let b = Box::new_in([0u8; 64], &heap);
+    assert!(b.iter().all(|x| *x == 0));
+}
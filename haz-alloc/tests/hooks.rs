@@ -0,0 +1,121 @@
+use haz_alloc::{Alloc, AllocHooks, BlockKind};
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Mutex;
+
+static ALLOC: Alloc<Recorder> = Alloc::with_hooks();
+static COUNTED: Alloc<Counter> = Alloc::with_hooks();
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Alloc(usize, usize, BlockKind),
+    Dealloc(usize, usize, BlockKind),
+    Realloc(usize, usize, usize, BlockKind),
+}
+
+static EVENTS: Mutex<Vec<Event>> = Mutex::new(Vec::new());
+
+struct Recorder;
+
+impl Recorder {
+    fn record(event: Event) {
+        // Allocating from a hook does not call the hooks again.
+        unsafe { ALLOC.dealloc(ALLOC.alloc(Layout::from_size_align(8, 8).unwrap())) };
+        EVENTS.lock().unwrap().push(event);
+    }
+}
+
+impl AllocHooks for Recorder {
+    fn alloc(ptr: *mut u8, size: usize, kind: BlockKind) {
+        Self::record(Event::Alloc(ptr as usize, size, kind));
+    }
+
+    fn dealloc(ptr: *mut u8, size: usize, kind: BlockKind) {
+        Self::record(Event::Dealloc(ptr as usize, size, kind));
+    }
+
+    fn realloc(old: *mut u8, new: *mut u8, size: usize, kind: BlockKind) {
+        Self::record(Event::Realloc(old as usize, new as usize, size, kind));
+    }
+}
+
+// Bytes of live blocks of `COUNTED`
+static LIVE: AtomicIsize = AtomicIsize::new(0);
+
+struct Counter;
+
+impl AllocHooks for Counter {
+    fn alloc(_: *mut u8, size: usize, _: BlockKind) {
+        LIVE.fetch_add(size as isize, Ordering::Relaxed);
+    }
+
+    fn dealloc(_: *mut u8, size: usize, _: BlockKind) {
+        LIVE.fetch_sub(size as isize, Ordering::Relaxed);
+    }
+}
+
+// Events are global, so everything is checked from a single test.
+#[test]
+fn test_hooks() {
+    unsafe {
+        let p = ALLOC.alloc(Layout::from_size_align(48, 8).unwrap());
+        let q = ALLOC.realloc(p, Layout::from_size_align(100000, 8).unwrap());
+        let grown = ALLOC.size(q);
+        assert_eq!(
+            ALLOC.shrink_in_place(q, Layout::from_size_align(90000, 8).unwrap()),
+            Some(ALLOC.size(q))
+        );
+        let size = ALLOC.size(q);
+        ALLOC.dealloc(q);
+
+        let mut blocks = [std::ptr::null_mut(); 2];
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        assert_eq!(ALLOC.alloc_batch(layout, &mut blocks), 2);
+        ALLOC.dealloc_batch(&blocks);
+
+        let (p, q) = (p as usize, q as usize);
+        let (a, b) = (blocks[0] as usize, blocks[1] as usize);
+        assert_eq!(
+            *EVENTS.lock().unwrap(),
+            [
+                Event::Alloc(p, 48, BlockKind::Small),
+                Event::Realloc(p, q, grown, BlockKind::Large),
+                Event::Realloc(q, q, size, BlockKind::Large),
+                Event::Dealloc(q, size, BlockKind::Large),
+                Event::Alloc(a, 16, BlockKind::Small),
+                Event::Alloc(b, 16, BlockKind::Small),
+                Event::Dealloc(a, 16, BlockKind::Small),
+                Event::Dealloc(b, 16, BlockKind::Small),
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_hooks_sizes_match() {
+    // Sizes given to the hooks add up, whether blocks are deallocated with
+    // their layout or not.
+    unsafe {
+        let layouts: Vec<_> = [20, 100, 3000, 5000, 100000, 300000]
+            .iter()
+            .map(|size| Layout::from_size_align(*size, 8).unwrap())
+            .collect();
+        for (i, layout) in layouts.iter().enumerate() {
+            let p = COUNTED.alloc(*layout);
+            let q = COUNTED.alloc(*layout);
+            assert!(LIVE.load(Ordering::Relaxed) > 0);
+            COUNTED.dealloc(p);
+            COUNTED.dealloc_sized(q, *layout);
+            assert_eq!(LIVE.load(Ordering::Relaxed), 0, "{}", i);
+        }
+
+        let layout = layouts[0];
+        let mut blocks = [std::ptr::null_mut(); 8];
+        assert_eq!(COUNTED.alloc_batch(layout, &mut blocks), blocks.len());
+        COUNTED.dealloc_batch(&blocks[..4]);
+        for p in &blocks[4..] {
+            COUNTED.dealloc_sized(*p, layout);
+        }
+        assert_eq!(LIVE.load(Ordering::Relaxed), 0);
+    }
+}
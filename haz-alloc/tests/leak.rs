@@ -0,0 +1,79 @@
+#[cfg(feature = "leak_report")]
+mod common;
+
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+// Blocks are global, so the report is checked from a single test, apart
+// from the child process below.
+#[test]
+fn test_leak_report() {
+    unsafe {
+        let mut report = String::new();
+        let before = ALLOC.leak_report(&mut report).unwrap();
+
+        let small: Vec<_> = (0..3)
+            .map(|_| ALLOC.alloc(Layout::from_size_align(48, 8).unwrap()))
+            .collect();
+        let large = ALLOC.alloc(Layout::from_size_align(100000, 8).unwrap());
+        let huge = ALLOC.alloc(Layout::from_size_align(64 * 1024 * 1024, 8).unwrap());
+
+        let mut report = String::new();
+        assert_eq!(ALLOC.leak_report(&mut report).unwrap(), before + 5);
+        assert!(report.starts_with("haz-alloc: leak report\n"));
+        assert!(report.contains("  small 48: 3 blocks, 144 bytes\n"));
+        assert!(report.contains(&format!("  large: 1 blocks, {} bytes\n", ALLOC.size(large))));
+        assert!(report.contains(&format!("huge: 1 blocks, {} bytes\n", ALLOC.size(huge))));
+        assert!(report.contains("total: "));
+
+        for p in small {
+            ALLOC.dealloc(p);
+        }
+        ALLOC.dealloc(large);
+        ALLOC.dealloc(huge);
+
+        let mut report = String::new();
+        assert_eq!(ALLOC.leak_report(&mut report).unwrap(), before);
+        if before == 0 {
+            assert!(report.is_empty());
+        }
+    }
+}
+
+#[cfg(feature = "leak_report")]
+#[test]
+fn test_leak_report_at_exit() {
+    if std::env::var_os("HAZ_ALLOC_LEAK_CHILD").is_some() {
+        // Leak from the child process.
+        unsafe { ALLOC.alloc(Layout::from_size_align(48, 8).unwrap()) };
+        return;
+    }
+
+    let (status, stderr) = common::run_child(
+        "test_leak_report_at_exit",
+        &[
+            ("HAZ_ALLOC_LEAK_CHILD", "1"),
+            ("HAZ_ALLOC_LEAK_REPORT", "1"),
+        ],
+    );
+    assert!(status.success());
+    assert!(stderr.contains("haz-alloc: leak report\n"), "{}", stderr);
+    assert!(
+        stderr.contains("  small 48: 1 blocks, 48 bytes\n"),
+        "{}",
+        stderr
+    );
+
+    // Nothing is reported unless enabled.
+    let (status, stderr) = common::run_child(
+        "test_leak_report_at_exit",
+        &[
+            ("HAZ_ALLOC_LEAK_CHILD", "1"),
+            ("HAZ_ALLOC_LEAK_REPORT", "0"),
+        ],
+    );
+    assert!(status.success());
+    assert!(!stderr.contains("leak report"));
+}
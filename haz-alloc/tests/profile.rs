@@ -0,0 +1,50 @@
+#![cfg(feature = "profiling")]
+
+use haz_alloc::{dump_profile, set_profile_rate, Alloc};
+use std::alloc::Layout;
+
+// Dumping allocates, so allocations made while sampling go through the
+// profiler too.
+#[global_allocator]
+static ALLOC: Alloc = Alloc::new();
+
+fn dump() -> String {
+    let mut profile = Vec::new();
+    dump_profile(&mut profile).unwrap();
+    String::from_utf8(profile).unwrap()
+}
+
+// The table is global, so everything is checked from a single test.
+#[test]
+fn test_profile() {
+    unsafe {
+        let layout = Layout::from_size_align(12345, 8).unwrap();
+
+        // Nothing is sampled by default.
+        let p = ALLOC.alloc(layout);
+        assert!(!dump().contains(": 12345 ["));
+        ALLOC.dealloc(p);
+
+        // With a rate of 1 byte, every allocation is sampled.
+        set_profile_rate(1);
+        let p = ALLOC.alloc(layout);
+        let q = ALLOC.alloc(layout);
+        set_profile_rate(0);
+
+        let profile = dump();
+        assert!(profile.starts_with("heap profile: "), "{}", profile);
+        assert!(profile.lines().next().unwrap().ends_with(" @ heap_v2/1"));
+        let line = profile
+            .lines()
+            .find(|x| x.contains(": 12345 ["))
+            .expect(&profile);
+        assert!(line.starts_with("1: 12345 [1: 12345] @ 0x"), "{}", line);
+        #[cfg(target_os = "linux")]
+        assert!(profile.contains("\nMAPPED_LIBRARIES:\n"));
+
+        // Freed blocks leave the profile.
+        ALLOC.dealloc(p);
+        ALLOC.dealloc(q);
+        assert!(!dump().contains(": 12345 ["));
+    }
+}
@@ -0,0 +1,61 @@
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+static ALLOC: Alloc = Alloc::new();
+
+// Arenas are global, so everything is checked from a single test.
+#[test]
+fn test_purge() {
+    unsafe {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        // Exited threads leave their empty arenas in the pool.
+        let barrier = Arc::new(Barrier::new(4));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let ptrs: Vec<_> = (0..1000).map(|_| ALLOC.alloc(layout)).collect();
+                    // Keep every thread alive until they all have an arena.
+                    barrier.wait();
+                    for p in ptrs {
+                        ALLOC.dealloc(p);
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(ALLOC.stats().arenas_parked >= 4);
+        assert!(ALLOC.purge() > 0);
+        assert_eq!(ALLOC.stats().arenas_parked, 0);
+        assert_eq!(ALLOC.purge(), 0);
+
+        // Arenas with live blocks are kept.
+        let p = thread::spawn(move || ALLOC.alloc(layout) as usize)
+            .join()
+            .unwrap() as *mut u8;
+        assert_eq!(ALLOC.purge(), 0);
+        assert_eq!(ALLOC.stats().arenas_parked, 1);
+        ALLOC.dealloc(p);
+        assert!(ALLOC.purge() > 0);
+
+        // Flushing gives up the arena of the current thread.
+        let p = ALLOC.alloc(layout);
+        ALLOC.dealloc(p);
+        let reserved = ALLOC.stats().reserved;
+        assert!(ALLOC.flush() > 0);
+        assert!(ALLOC.stats().reserved < reserved);
+        assert_eq!(ALLOC.flush(), 0);
+
+        // A flushed thread can still allocate and free blocks.
+        let p = ALLOC.alloc(layout);
+        assert!(!p.is_null());
+        assert_eq!(ALLOC.flush(), 0);
+        ALLOC.dealloc(p);
+        assert!(ALLOC.purge() > 0);
+    }
+}
@@ -0,0 +1,68 @@
+#![cfg(feature = "quarantine")]
+
+mod common;
+
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+#[test]
+fn test_quarantine() {
+    unsafe {
+        // Freed blocks are not handed out again while held.
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let p = ALLOC.alloc(layout);
+        ALLOC.dealloc(p);
+        let ptrs: Vec<_> = (0..100).map(|_| ALLOC.alloc(layout)).collect();
+        assert!(!ptrs.contains(&p));
+        for q in ptrs {
+            ALLOC.dealloc(q);
+        }
+
+        // Held blocks count as live until purged.
+        let before = ALLOC.stats().large;
+        let large = ALLOC.alloc(Layout::from_size_align(100000, 8).unwrap());
+        ALLOC.dealloc(large);
+        assert_eq!(ALLOC.stats().large, before + 1);
+        ALLOC.purge();
+        assert_eq!(ALLOC.stats().large, before);
+
+        // Blocks larger than the quarantine are freed at once.
+        ALLOC.set_quarantine_size(1000);
+        let large = ALLOC.alloc(Layout::from_size_align(100000, 8).unwrap());
+        ALLOC.dealloc(large);
+        assert_eq!(ALLOC.stats().large, before);
+    }
+}
+
+#[test]
+fn test_write_after_free() {
+    if let Some(case) = std::env::var_os("HAZ_ALLOC_QUARANTINE_CHILD") {
+        // Write to freed blocks from the child process.
+        unsafe {
+            let size = match case.to_str().unwrap() {
+                "small" => 48,
+                "large" => 100000,
+                _ => unreachable!(),
+            };
+            let p = ALLOC.alloc(Layout::from_size_align(size, 8).unwrap());
+            ALLOC.dealloc(p);
+            p.add(10).write(0);
+            ALLOC.purge();
+        }
+        return;
+    }
+
+    for (case, message) in [
+        ("small", "written at offset 10 after free, in small class"),
+        ("large", "written at offset 10 after free, in large block"),
+    ] {
+        let (status, stderr) = common::run_child(
+            "test_write_after_free",
+            &[("HAZ_ALLOC_QUARANTINE_CHILD", case)],
+        );
+        assert!(!status.success());
+        assert!(stderr.contains(message), "{}", stderr);
+    }
+}
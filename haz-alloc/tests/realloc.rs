@@ -1,4 +1,3 @@
-#![feature(test)]
 #![allow(clippy::all)]
 
 use haz_alloc::Alloc;
@@ -48,3 +47,70 @@ fn test_huge_to_small() {
         ALLOC.dealloc(p as _);
     }
 }
+
+#[test]
+fn test_in_place() {
+    unsafe {
+        let p = ALLOC.alloc(Layout::from_size_align(8, 8).unwrap());
+        assert_eq!(
+            ALLOC.try_grow_in_place(p, Layout::from_size_align(8, 8).unwrap()),
+            Some(8)
+        );
+        assert_eq!(
+            ALLOC.try_grow_in_place(p, Layout::from_size_align(64, 8).unwrap()),
+            None
+        );
+        assert_eq!(ALLOC.size(p), 8);
+        ALLOC.dealloc(p);
+
+        // Growing within the slack of a block always succeeds.
+        let p = ALLOC.alloc(Layout::from_size_align(5, 1).unwrap());
+        let size = ALLOC
+            .try_grow_in_place(p, Layout::from_size_align(6, 1).unwrap())
+            .unwrap();
+        assert!(size >= 6);
+        assert_eq!(ALLOC.size(p), size);
+        ALLOC.dealloc(p);
+
+        // Neither goes the wrong way.
+        let p = ALLOC.alloc(Layout::from_size_align(100000, 8).unwrap());
+        let size = ALLOC
+            .try_grow_in_place(p, Layout::from_size_align(100001, 8).unwrap())
+            .unwrap();
+        assert!(size >= 100001);
+        assert_eq!(
+            ALLOC.try_grow_in_place(p, Layout::from_size_align(50000, 8).unwrap()),
+            Some(size)
+        );
+        assert_eq!(ALLOC.size(p), size);
+        assert_eq!(
+            ALLOC.shrink_in_place(p, Layout::from_size_align(150000, 8).unwrap()),
+            None
+        );
+        assert_eq!(ALLOC.size(p), size);
+        ALLOC.dealloc(p);
+
+        let p = ALLOC.alloc(Layout::from_size_align(6553600, 8).unwrap());
+        *p = 100;
+        let size = ALLOC
+            .shrink_in_place(p, Layout::from_size_align(400000, 8).unwrap())
+            .unwrap();
+        assert!(size >= 400000 && size < 6553600);
+        assert_eq!(ALLOC.size(p), size);
+        // The reservation still has room for the original size.
+        let size = ALLOC
+            .try_grow_in_place(p, Layout::from_size_align(6553600, 8).unwrap())
+            .unwrap();
+        assert!(size >= 6553600);
+        assert_eq!(ALLOC.size(p), size);
+        *p.add(6553599) = 1;
+        assert_eq!(*p, 100);
+
+        // Huge blocks do not become small in place.
+        assert_eq!(
+            ALLOC.shrink_in_place(p, Layout::from_size_align(16, 8).unwrap()),
+            None
+        );
+        ALLOC.dealloc(p);
+    }
+}
@@ -0,0 +1,79 @@
+use haz_alloc::Alloc;
+use std::alloc::Layout;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static ALLOC: Alloc = Alloc::new();
+
+const BATCH: usize = 256;
+
+/// Allocate `rounds` batches of blocks in a producer thread and free them in
+/// a consumer thread with `free`, returning the time taken.
+fn pipeline(rounds: usize, free: fn(*mut u8)) -> Duration {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let (tx, rx) = mpsc::sync_channel::<Vec<usize>>(16);
+
+    let start = Instant::now();
+    let producer = thread::spawn(move || {
+        for i in 0..rounds {
+            let batch = (0..BATCH)
+                .map(|_| unsafe {
+                    let p = ALLOC.alloc(layout);
+                    assert!(!p.is_null());
+                    *(p as *mut usize) = i;
+                    p as usize
+                })
+                .collect();
+            tx.send(batch).unwrap();
+        }
+    });
+    let consumer = thread::spawn(move || {
+        for (i, batch) in rx.iter().enumerate() {
+            for p in batch {
+                unsafe { assert_eq!(*(p as *mut usize), i) };
+                free(p as *mut u8);
+            }
+        }
+    });
+    producer.join().unwrap();
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+#[test]
+fn test_remote_free() {
+    // Blocks in the quarantine count as live.
+    #[cfg(feature = "quarantine")]
+    ALLOC.set_quarantine_size(0);
+
+    let before = ALLOC.stats();
+    pipeline(1000, |p| unsafe { ALLOC.dealloc(p) });
+
+    // The producer exited, so the blocks left in its queue are freed too.
+    let stats = ALLOC.stats();
+    assert_eq!(stats.small_total(), before.small_total());
+    assert!(ALLOC.purge() > 0);
+}
+
+// Run with `cargo test --release --test remote -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_remote_free() {
+    const ROUNDS: usize = 20000;
+
+    // Freeing a single block as a batch goes straight to its page, as every
+    // remote free did before the queue.
+    let page = pipeline(ROUNDS, |p| unsafe { ALLOC.dealloc_batch(&[p]) });
+    let queue = pipeline(ROUNDS, |p| unsafe { ALLOC.dealloc(p) });
+
+    let ops = (ROUNDS * BATCH) as f64;
+    println!(
+        "remote free through the page: {:.1} ns/block",
+        page.as_nanos() as f64 / ops
+    );
+    println!(
+        "remote free through the queue: {:.1} ns/block",
+        queue.as_nanos() as f64 / ops
+    );
+}
@@ -0,0 +1,69 @@
+use haz_alloc::{Alloc, SizeClass};
+use std::alloc::Layout;
+use std::thread;
+
+static ALLOC: Alloc = Alloc::new();
+
+// Statistics are global, so everything is checked from a single test.
+#[test]
+fn test_stats() {
+    // Blocks in the quarantine count as live.
+    #[cfg(feature = "quarantine")]
+    ALLOC.set_quarantine_size(0);
+
+    unsafe {
+        // In debug mode, blocks are padded with a redzone and a trailer.
+        let padding = if cfg!(feature = "debug") { 24 } else { 0 };
+        let class = SizeClass::of(48 + padding).unwrap();
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        // Make sure the thread has an arena before taking the baseline.
+        ALLOC.dealloc(ALLOC.alloc(layout));
+        let before = ALLOC.stats();
+
+        let small: Vec<_> = (0..100).map(|_| ALLOC.alloc(layout)).collect();
+        let large = ALLOC.alloc(Layout::from_size_align(100000, 8).unwrap());
+        let huge = ALLOC.alloc(Layout::from_size_align(64 * 1024 * 1024, 8).unwrap());
+        assert!(!large.is_null() && !huge.is_null());
+
+        let stats = ALLOC.stats();
+        assert_eq!(
+            stats.small[class.index()],
+            before.small[class.index()] + 100 * class.size()
+        );
+        assert_eq!(stats.large, before.large + 1);
+        assert_eq!(stats.huge, before.huge + 1);
+        assert!(stats.reserved >= before.reserved + 64 * 1024 * 1024);
+        assert!(stats.committed_pages > 100000 / 4096);
+        assert!(stats.arenas_in_use >= 1);
+
+        let mut small_total = 0;
+        let mut committed_pages = 0;
+        ALLOC.arena_stats(|arena| {
+            small_total += arena.small.iter().sum::<usize>();
+            committed_pages += arena.committed_pages;
+        });
+        assert_eq!(small_total, stats.small_total());
+        assert_eq!(committed_pages, stats.committed_pages);
+
+        for p in small {
+            ALLOC.dealloc(p);
+        }
+        ALLOC.dealloc(large);
+        ALLOC.dealloc(huge);
+
+        let stats = ALLOC.stats();
+        assert_eq!(stats.small, before.small);
+        assert_eq!(stats.large, before.large);
+        assert_eq!(stats.huge, before.huge);
+        assert_eq!(stats.reserved, before.reserved);
+
+        // The arena of an exited thread is parked for the next one.
+        thread::spawn(move || {
+            let p = ALLOC.alloc(layout);
+            ALLOC.dealloc(p);
+        })
+        .join()
+        .unwrap();
+        assert!(ALLOC.stats().arenas_parked >= 1);
+    }
+}
@@ -1,4 +1,4 @@
-#![feature(test)]
+#![allow(clippy::needless_range_loop)]
 
 use haz_alloc::Alloc;
 
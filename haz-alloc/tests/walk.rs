@@ -0,0 +1,86 @@
+use haz_alloc::{Alloc, BlockKind, Heap};
+use std::alloc::Layout;
+
+static ALLOC: Alloc = Alloc::new();
+
+/// Returns the kind and usable size of every block found by `walk` among
+/// `ptrs`.
+fn find(ptrs: &[*mut u8]) -> Vec<Option<(BlockKind, usize)>> {
+    let mut found = vec![None; ptrs.len()];
+    ALLOC.walk(|info| {
+        if let Some(i) = ptrs.iter().position(|p| *p == info.ptr) {
+            assert!(found[i].is_none());
+            found[i] = Some((info.kind, info.size));
+        }
+    });
+    found
+}
+
+// Blocks are global, so everything is checked from a single test.
+#[test]
+fn test_walk() {
+    unsafe {
+        let sizes = [8, 48, 1000, 100000, 64 * 1024 * 1024];
+        let kinds = [
+            BlockKind::Small,
+            BlockKind::Small,
+            BlockKind::Small,
+            BlockKind::Large,
+            BlockKind::Huge,
+        ];
+        let ptrs: Vec<_> = sizes
+            .iter()
+            .map(|size| ALLOC.alloc(Layout::from_size_align(*size, 8).unwrap()))
+            .collect();
+        let aligned = ALLOC.alloc(Layout::from_size_align(100000, 4096).unwrap());
+        assert!(!aligned.is_null());
+
+        let found = find(&ptrs);
+        for i in 0..ptrs.len() {
+            assert_eq!(found[i], Some((kinds[i], ALLOC.size(ptrs[i]))));
+        }
+        assert_eq!(
+            find(&[aligned]),
+            vec![Some((BlockKind::Large, ALLOC.size(aligned)))]
+        );
+
+        // Freed blocks are not reported, even if cached by the thread.
+        for p in &ptrs {
+            ALLOC.dealloc(*p);
+        }
+        ALLOC.dealloc(aligned);
+        assert!(find(&ptrs).iter().all(|x| x.is_none()));
+
+        // Every live block is found, neighbours of freed slots included.
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut ptrs: Vec<_> = (0..1000).map(|_| ALLOC.alloc(layout)).collect();
+        for p in ptrs.drain(..500).step_by(2) {
+            ALLOC.dealloc(p);
+        }
+        assert!(find(&ptrs).iter().all(|x| x.is_some()));
+        for p in ptrs {
+            ALLOC.dealloc(p);
+        }
+
+        // Slabs of larger classes span several pages, all of them walked.
+        let layout = Layout::from_size_align(3000, 8).unwrap();
+        let ptrs: Vec<_> = (0..100).map(|_| ALLOC.alloc(layout)).collect();
+        let found = find(&ptrs);
+        for i in 0..ptrs.len() {
+            assert_eq!(found[i], Some((BlockKind::Small, ALLOC.size(ptrs[i]))));
+        }
+        for p in ptrs {
+            ALLOC.dealloc(p);
+        }
+
+        // Blocks of heaps are found too.
+        let heap = Heap::new().unwrap();
+        let small = heap.alloc(layout);
+        let huge = heap.alloc(Layout::from_size_align(64 * 1024 * 1024, 8).unwrap());
+        let found = find(&[small, huge]);
+        assert_eq!(found[0].map(|x| x.0), Some(BlockKind::Small));
+        assert_eq!(found[1].map(|x| x.0), Some(BlockKind::Huge));
+        drop(heap);
+        assert!(find(&[small, huge]).iter().all(|x| x.is_none()));
+    }
+}